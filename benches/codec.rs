@@ -0,0 +1,129 @@
+/*
+ * uclcli benches/codec.rs - compression and decompression throughput
+ * Copyright (C) 2020-2021  BMW Group
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use uclcli::frame::compress_stream;
+use uclcli::{compress_with_level, decompress};
+
+const SIZES: [(&str, usize); 3] = [
+    ("4KiB", 4 * 1024),
+    ("1MiB", 1024 * 1024),
+    ("64MiB", 64 * 1024 * 1024),
+];
+const LEVELS: [u8; 3] = [1, 6, 10];
+
+const TEXT: &[u8] = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod \
+tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud \
+exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in \
+reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur.\n";
+
+fn zeroes(size: usize) -> Vec<u8> {
+    vec![0; size]
+}
+
+/// deterministic xorshift noise, incompressible for all practical purposes
+fn random(size: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..size)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+/// text with some variation, so it doesn't degrade into a trivial repetition
+fn text(size: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(size);
+    let mut line = 0usize;
+    while out.len() < size {
+        out.extend_from_slice(format!("{:08} ", line).as_bytes());
+        let skip = line * 7 % TEXT.len();
+        out.extend_from_slice(&TEXT[skip..]);
+        line += 1;
+    }
+    out.truncate(size);
+    out
+}
+
+type Generator = fn(usize) -> Vec<u8>;
+
+const INPUTS: [(&str, Generator); 3] = [("zeroes", zeroes), ("random", random), ("text", text)];
+
+fn compression(c: &mut Criterion) {
+    for (input_name, generate) in INPUTS.iter() {
+        let mut group = c.benchmark_group(format!("compress/{}", input_name));
+        group.sample_size(10);
+        for (size_name, size) in SIZES.iter() {
+            let data = generate(*size);
+            group.throughput(Throughput::Bytes(*size as u64));
+            for level in LEVELS.iter() {
+                group.bench_with_input(
+                    BenchmarkId::new(format!("level {}", level), size_name),
+                    &data,
+                    |b, data| b.iter(|| compress_with_level(data, *level).unwrap()),
+                );
+            }
+        }
+        group.finish();
+    }
+}
+
+fn decompression(c: &mut Criterion) {
+    for (input_name, generate) in INPUTS.iter() {
+        let mut group = c.benchmark_group(format!("decompress/{}", input_name));
+        group.sample_size(10);
+        for (size_name, size) in SIZES.iter() {
+            let data = generate(*size);
+            group.throughput(Throughput::Bytes(*size as u64));
+            for level in LEVELS.iter() {
+                let compressed = compress_with_level(&data, *level).unwrap();
+                group.bench_with_input(
+                    BenchmarkId::new(format!("level {}", level), size_name),
+                    &compressed,
+                    |b, compressed| b.iter(|| decompress(compressed, *size as u32).unwrap()),
+                );
+            }
+        }
+        group.finish();
+    }
+}
+
+/// framed stream compression, compare runs with and without the `rayon` feature
+fn stream_compression(c: &mut Criterion) {
+    const SIZE: usize = 256 * 1024 * 1024;
+    let data = text(SIZE);
+
+    let mut group = c.benchmark_group("compress_stream/text");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(SIZE as u64));
+    group.bench_function("256MiB", |b| {
+        let mut out = Vec::with_capacity(SIZE);
+        b.iter(|| {
+            out.clear();
+            compress_stream(&mut &data[..], &mut out, 6).unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, compression, decompression, stream_compression);
+criterion_main!(benches);
@@ -0,0 +1,90 @@
+/*
+ * uclcli benches/compressor.rs - small block benchmarks
+ * Copyright (C) 2020-2021  BMW Group
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use uclcli::{
+    compress, decompress_into_buffer, decompress_into_buffer_unchecked, ucl_init, Compressor,
+};
+
+const BLOCK_SIZE: usize = 512;
+const BLOCKS: usize = 1000;
+
+fn small_blocks(c: &mut Criterion) {
+    let data: Vec<u8> = (0..BLOCK_SIZE * BLOCKS)
+        .map(|i| (i % 251) as u8 ^ (i / 4096) as u8)
+        .collect();
+
+    let mut group = c.benchmark_group("small blocks");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+
+    group.bench_function("compress", |b| {
+        b.iter(|| {
+            for block in data.chunks(BLOCK_SIZE) {
+                compress(block).unwrap();
+            }
+        })
+    });
+
+    let mut compressor = Compressor::new();
+    group.bench_function("Compressor::compress_into", |b| {
+        b.iter(|| {
+            for block in data.chunks(BLOCK_SIZE) {
+                compressor.compress_into(block).unwrap();
+            }
+        })
+    });
+
+    group.finish();
+}
+
+fn small_block_decompression(c: &mut Criterion) {
+    ucl_init();
+    let data: Vec<u8> = (0..BLOCK_SIZE * BLOCKS)
+        .map(|i| (i % 251) as u8 ^ (i / 4096) as u8)
+        .collect();
+    let blocks: Vec<Vec<u8>> = data
+        .chunks(BLOCK_SIZE)
+        .map(|block| compress(block).unwrap())
+        .collect();
+    let mut buf = [0u8; BLOCK_SIZE];
+
+    let mut group = c.benchmark_group("small block decompression");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+
+    group.bench_function("decompress_into_buffer", |b| {
+        b.iter(|| {
+            for block in &blocks {
+                decompress_into_buffer(block, &mut buf).unwrap();
+            }
+        })
+    });
+
+    group.bench_function("decompress_into_buffer_unchecked", |b| {
+        b.iter(|| {
+            for block in &blocks {
+                decompress_into_buffer_unchecked(block, &mut buf).unwrap();
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, small_blocks, small_block_decompression);
+criterion_main!(benches);
@@ -1,3 +1,26 @@
 fn main() {
     println!("cargo:rustc-link-lib=ucl");
+
+    #[cfg(feature = "bindgen")]
+    generate_bindings();
+}
+
+/// generate FFI declarations for the installed libucl headers into `$OUT_DIR/bindings.rs`
+#[cfg(feature = "bindgen")]
+fn generate_bindings() {
+    let out_path = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap());
+
+    bindgen::Builder::default()
+        .header_contents("wrapper.h", "#include <ucl/ucl.h>\n")
+        // the crate may be built without std
+        .use_core()
+        .ctypes_prefix("libc")
+        .allowlist_function("ucl_.*|__ucl_init2")
+        .allowlist_type("ucl_.*")
+        .allowlist_var("UCL_.*")
+        .parse_callbacks(Box::new(bindgen::CargoCallbacks::new()))
+        .generate()
+        .expect("failed to generate libucl bindings, are the ucl headers installed?")
+        .write_to_file(out_path.join("bindings.rs"))
+        .expect("failed to write libucl bindings");
 }
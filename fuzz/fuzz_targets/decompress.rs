@@ -1,5 +1,4 @@
 #![no_main]
-use std::convert::TryInto;
 use libfuzzer_sys::fuzz_target;
 
 use uclcli::{ucl_init, decompress};
@@ -7,5 +6,7 @@ use uclcli::{ucl_init, decompress};
 fuzz_target!(|data: &[u8]| {
     ucl_init();
 
-    let _result = decompress(data, (data.len() * 1024).try_into().unwrap());
+    // clamp instead of panicking, so crashes are always in the library, not the harness
+    let capacity = data.len().saturating_mul(1024).min(u32::MAX as usize) as u32;
+    let _result = decompress(data, capacity);
 });
@@ -0,0 +1,15 @@
+#![no_main]
+use std::io;
+use libfuzzer_sys::fuzz_target;
+
+use uclcli::frame::decompress_stream;
+use uclcli::io::UclReader;
+use uclcli::ucl_init;
+
+fuzz_target!(|data: &[u8]| {
+    ucl_init();
+
+    let _result = decompress_stream(&mut &data[..], &mut io::sink());
+    // skip the stream header, so every input reaches the block parser
+    let _result = io::copy(&mut UclReader::new(data), &mut io::sink());
+});
@@ -0,0 +1,17 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use uclcli::frame::{compress_stream, decompress_stream};
+use uclcli::{ucl_init, DEFAULT_LEVEL};
+
+fuzz_target!(|data: &[u8]| {
+    ucl_init();
+
+    let mut compressed = Vec::new();
+    compress_stream(&mut &data[..], &mut compressed, DEFAULT_LEVEL).unwrap();
+
+    let mut decompressed = Vec::new();
+    let numbytes = decompress_stream(&mut &compressed[..], &mut decompressed).unwrap();
+    assert_eq!(numbytes, data.len() as u64);
+    assert_eq!(decompressed, data);
+});
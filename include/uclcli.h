@@ -0,0 +1,87 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+#ifndef UCLCLI_H
+#define UCLCLI_H
+
+/* generated by cbindgen from src/capi.rs, do not edit */
+
+#include <stddef.h>
+#include <stdint.h>
+
+// Status codes returned by the C API.
+//
+// The values are stable, new codes are only ever added at the end.
+typedef enum UclcliStatus {
+  // success
+  UCLCLI_STATUS_OK = 0,
+  // libucl cannot be initialized, e.g. because the linked version is incompatible
+  UCLCLI_STATUS_INIT_FAILED = 1,
+  // an unspecified libucl error
+  UCLCLI_STATUS_GENERIC_ERROR = 2,
+  // an argument is invalid, e.g. a required pointer is null
+  UCLCLI_STATUS_INVALID_ARGUMENT = 3,
+  // libucl ran out of memory
+  UCLCLI_STATUS_OUT_OF_MEMORY = 4,
+  // the input cannot be compressed
+  UCLCLI_STATUS_NOT_COMPRESSIBLE = 5,
+  // the compressed data is truncated
+  UCLCLI_STATUS_INPUT_OVERRUN = 6,
+  // the decompressed data does not fit into the destination buffer
+  UCLCLI_STATUS_OUTPUT_OVERRUN = 7,
+  // the compressed data refers to data before the start of the output
+  UCLCLI_STATUS_LOOKBEHIND_OVERRUN = 8,
+  // the compressed data has no end marker
+  UCLCLI_STATUS_EOF_NOT_FOUND = 9,
+  // the input continues after the end of the compressed data
+  UCLCLI_STATUS_INPUT_NOT_CONSUMED = 10,
+  // the compressed data overlaps the output too much for in-place decompression
+  UCLCLI_STATUS_OVERLAP_OVERRUN = 11,
+  // the source buffer is larger than 4 GiB
+  UCLCLI_STATUS_SRC_TOO_LARGE = 12,
+  // the destination buffer is larger than 4 GiB
+  UCLCLI_STATUS_DST_TOO_LARGE = 13,
+  // the destination buffer is too small, the required size is stored in the length argument
+  UCLCLI_STATUS_DST_TOO_SMALL = 14,
+  // the decompressed data does not match its recorded checksum
+  UCLCLI_STATUS_CHECKSUM_MISMATCH = 15,
+  // any other error
+  UCLCLI_STATUS_UNKNOWN = 255,
+} UclcliStatus;
+
+// Initialize libucl.
+//
+// Calling this is optional, all other functions initialize libucl on first use. It is safe to
+// call this any number of times from any number of threads.
+enum UclcliStatus uclcli_init(void);
+
+// Size of the destination buffer required to compress `src_len` bytes.
+size_t uclcli_compress_bound(size_t src_len);
+
+// Compress `src_len` bytes at `src` into the buffer `dst` of `*dst_len` bytes.
+//
+// On success, the compressed size is stored in `*dst_len`. If the buffer is too small,
+// `UCLCLI_STATUS_DST_TOO_SMALL` is returned and the required size is stored in `*dst_len`, see
+// also `uclcli_compress_bound`.
+//
+// # Safety
+// `src` must be valid for reads of `src_len` bytes and `dst` must be valid for writes of
+// `*dst_len` bytes. Either may be null if its length is zero. `dst_len` must not be null.
+enum UclcliStatus uclcli_compress(const uint8_t *src,
+                                  size_t src_len,
+                                  uint8_t *dst,
+                                  size_t *dst_len);
+
+// Decompress `src_len` bytes at `src` into the buffer `dst` of `*dst_len` bytes.
+//
+// On success, the decompressed size is stored in `*dst_len`. If the buffer is too small,
+// `UCLCLI_STATUS_OUTPUT_OVERRUN` is returned.
+//
+// # Safety
+// `src` must be valid for reads of `src_len` bytes and `dst` must be valid for writes of
+// `*dst_len` bytes. Either may be null if its length is zero. `dst_len` must not be null.
+enum UclcliStatus uclcli_decompress(const uint8_t *src,
+                                    size_t src_len,
+                                    uint8_t *dst,
+                                    size_t *dst_len);
+
+#endif  /* UCLCLI_H */
@@ -0,0 +1,456 @@
+/*
+ * uclcli common/mod.rs - helpers shared by the ucl and unucl binaries
+ * Copyright (C) 2020-2021  BMW Group
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::error::Error;
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::os::raw::{c_char, c_int};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use anyhow::{bail, Context, Result};
+use uclcli::UclErrorKind;
+
+/// path of the temporary output file currently being written, removed on interruption
+static PENDING_TMP: AtomicPtr<c_char> = AtomicPtr::new(ptr::null_mut());
+
+/// exit status on interruption, as set by shells for processes killed by SIGINT
+const INTERRUPTED: c_int = 128 + libc::SIGINT;
+/// exit status for I/O errors and any failure not covered by the other statuses
+pub const EXIT_IO: i32 = 1;
+/// exit status for corrupt or otherwise undecodable input
+pub const EXIT_CORRUPT: i32 = 2;
+/// exit status for invalid command line arguments and arguments or input sizes libucl rejects
+pub const EXIT_USAGE: i32 = 3;
+
+extern "C" fn on_interrupt(_signal: c_int) {
+    // only async-signal-safe calls are allowed here
+    let tmp = PENDING_TMP.load(Ordering::SeqCst);
+    unsafe {
+        if !tmp.is_null() {
+            libc::unlink(tmp);
+        }
+        libc::_exit(INTERRUPTED);
+    }
+}
+
+/// remove the temporary output file before exiting on SIGINT or SIGTERM
+///
+/// Together with [OutputFile], this makes sure an interrupted run leaves behind either the
+/// old output file or none at all, never a partially written one.
+pub fn install_signal_handler() {
+    let handler = on_interrupt as extern "C" fn(c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+/// make `tmp` the file removed on interruption, or none if not given
+fn set_pending_tmp(tmp: Option<&Path>) {
+    // paths are leaked instead of freed, so the signal handler can never see a dangling
+    // pointer - there is only one per output file
+    let tmp = tmp
+        .and_then(|tmp| CString::new(tmp.as_os_str().as_encoded_bytes()).ok())
+        .map_or(ptr::null_mut(), CString::into_raw);
+    PENDING_TMP.store(tmp, Ordering::SeqCst);
+}
+
+/// An output file which is written under a temporary name next to its destination.
+///
+/// The file is only moved to its destination by [OutputFile::persist]. If it is dropped
+/// before that, the temporary file is removed, so a failed run never destroys an existing
+/// file.
+pub struct OutputFile {
+    path: PathBuf,
+    tmp: PathBuf,
+    file: Option<File>,
+}
+
+impl OutputFile {
+    /// create a temporary file for `path`
+    ///
+    /// Fails if `path` already exists, unless `force` is set.
+    pub fn create(path: &str, force: bool) -> Result<Self> {
+        let path = PathBuf::from(path);
+        if !force && path.exists() {
+            bail!(
+                "{}: output file already exists, use --force to overwrite",
+                path.display()
+            );
+        }
+
+        let mut name = path
+            .file_name()
+            .context("invalid output file name")?
+            .to_owned();
+        name.push(format!(".{}.tmp", process::id()));
+        let tmp = path.with_file_name(name);
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&tmp)
+            .context("could not create output file")?;
+        set_pending_tmp(Some(&tmp));
+
+        Ok(OutputFile {
+            path,
+            tmp,
+            file: Some(file),
+        })
+    }
+
+    /// the temporary file to write to
+    pub fn file(&self) -> &File {
+        self.file.as_ref().unwrap()
+    }
+
+    /// move the temporary file to its destination, replacing any existing file
+    pub fn persist(mut self) -> Result<()> {
+        drop(self.file.take());
+        let result =
+            fs::rename(&self.tmp, &self.path).context("could not move output file into place");
+        set_pending_tmp(None);
+        result
+    }
+}
+
+impl Drop for OutputFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.tmp);
+            set_pending_tmp(None);
+        }
+    }
+}
+
+/// run `f` on a writer to `path`, or to stdout if no path is given
+///
+/// Output files are only moved into place if `f` succeeds, see [OutputFile].
+pub fn write_output<F>(path: Option<&str>, force: bool, f: F) -> Result<()>
+where
+    F: FnOnce(&mut dyn Write) -> Result<()>,
+{
+    match path {
+        Some(path) => {
+            let out = OutputFile::create(path, force)?;
+            let mut writer = BufWriter::new(out.file());
+            f(&mut writer)?;
+            writer.flush().context("failed to write output")?;
+            drop(writer);
+            out.persist()
+        }
+        None => {
+            let mut stdout = io::stdout().lock();
+            f(&mut stdout)?;
+            stdout.flush().context("failed to write output")
+        }
+    }
+}
+
+/// a [Read] or [Write] adapter counting the bytes passing through it
+pub struct Counting<T> {
+    pub inner: T,
+    pub count: u64,
+}
+
+impl<T> Counting<T> {
+    pub fn new(inner: T) -> Self {
+        Counting { inner, count: 0 }
+    }
+}
+
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Counting<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// collect all regular files below `dir`, without following symlinks
+pub fn walk_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = fs::read_dir(&dir)
+            .with_context(|| format!("{}: could not read directory", dir.display()))?;
+        for entry in entries {
+            let entry =
+                entry.with_context(|| format!("{}: could not read directory", dir.display()))?;
+            let file_type = entry
+                .file_type()
+                .with_context(|| format!("{}: could not stat", entry.path().display()))?;
+            if file_type.is_dir() {
+                pending.push(entry.path());
+            } else if file_type.is_file() {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// totals over all files processed in recursive mode
+#[derive(Default)]
+pub struct Summary {
+    files: u64,
+    failed: u64,
+    status: i32,
+    uncompressed: u64,
+    compressed: u64,
+}
+
+impl Summary {
+    /// record a successfully processed file
+    pub fn add(&mut self, uncompressed: u64, compressed: u64) {
+        self.files += 1;
+        self.uncompressed += uncompressed;
+        self.compressed += compressed;
+    }
+
+    /// record a file which could not be processed
+    pub fn fail(&mut self, path: &Path, err: anyhow::Error) {
+        eprintln!("{}: {:#}", path.display(), err);
+        self.failed += 1;
+        self.status = self.status.max(exit_status(&err));
+    }
+
+    /// print the totals to stderr unless `quiet` is set, failing if any file could not be
+    /// processed
+    ///
+    /// The failure carries the highest exit status of all failed files.
+    pub fn finish(self, quiet: bool) -> Result<()> {
+        let ratio = if self.uncompressed == 0 {
+            100.0
+        } else {
+            self.compressed as f64 * 100.0 / self.uncompressed as f64
+        };
+        if !quiet {
+            eprintln!(
+                "{} files, {} bytes uncompressed, {} bytes compressed, ratio {:.1}%",
+                self.files, self.uncompressed, self.compressed, ratio
+            );
+        }
+        if self.failed > 0 {
+            bail!(Failure {
+                status: self.status,
+                message: format!("{} files failed", self.failed),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// an error reported with a specific exit status, see [exit_status]
+#[derive(Debug)]
+pub struct Failure {
+    pub status: i32,
+    pub message: String,
+}
+
+impl Failure {
+    /// an error in the command line arguments
+    pub fn usage(message: impl Into<String>) -> Self {
+        Failure {
+            status: EXIT_USAGE,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for Failure {}
+
+/// context for errors raised while decompressing input, see [exit_status]
+#[derive(Debug)]
+pub struct DecompressionFailed;
+
+impl fmt::Display for DecompressionFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("decompression failed")
+    }
+}
+
+/// the exit status for `err`, determined by the first cause of a known type
+///
+/// Decompression errors and malformed input are [EXIT_CORRUPT], arguments and sizes libucl
+/// rejects are [EXIT_USAGE] and everything else is [EXIT_IO] unless a [Failure] says otherwise.
+/// Within [DecompressionFailed], invalid arguments and sizes describe the input and are corrupt
+/// as well.
+pub fn exit_status(err: &anyhow::Error) -> i32 {
+    let decoding = err.downcast_ref::<DecompressionFailed>().is_some();
+    for cause in err.chain() {
+        if let Some(failure) = cause.downcast_ref::<Failure>() {
+            return failure.status;
+        }
+        let io_kind = match cause.downcast_ref::<io::Error>() {
+            Some(e) => match e.get_ref().and_then(|e| e.downcast_ref::<UclErrorKind>()) {
+                Some(kind) => return ucl_exit_status(kind, decoding),
+                None => e.kind(),
+            },
+            None => match cause.downcast_ref::<UclErrorKind>() {
+                Some(kind) => return ucl_exit_status(kind, decoding),
+                None => continue,
+            },
+        };
+        return io_exit_status(io_kind);
+    }
+    EXIT_IO
+}
+
+/// the exit status for a libucl error or an I/O error reported as one
+fn ucl_exit_status(kind: &UclErrorKind, decoding: bool) -> i32 {
+    match kind {
+        UclErrorKind::Io(io_kind) => io_exit_status(*io_kind),
+        UclErrorKind::InputOverrun
+        | UclErrorKind::OutputOverrun
+        | UclErrorKind::LookbehindOverrun
+        | UclErrorKind::EofNotFound
+        | UclErrorKind::InputNotConsumed
+        | UclErrorKind::OverlapOverrun
+        | UclErrorKind::ChecksumMismatch
+        | UclErrorKind::GenericError => EXIT_CORRUPT,
+        UclErrorKind::InvalidArgument
+        | UclErrorKind::SrcTooLarge
+        | UclErrorKind::DstTooLarge
+        | UclErrorKind::DstTooSmall { .. }
+            if decoding =>
+        {
+            EXIT_CORRUPT
+        }
+        UclErrorKind::InvalidArgument
+        | UclErrorKind::SrcTooLarge
+        | UclErrorKind::DstTooLarge
+        | UclErrorKind::DstTooSmall { .. } => EXIT_USAGE,
+        _ => EXIT_IO,
+    }
+}
+
+/// the exit status for an I/O error, treating invalid and truncated data as corrupt
+fn io_exit_status(kind: io::ErrorKind) -> i32 {
+    match kind {
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => EXIT_CORRUPT,
+        _ => EXIT_IO,
+    }
+}
+
+/// print `err` prefixed with the program name and exit with its [exit_status]
+///
+/// Success exits with status `0`.
+pub fn exit(name: &str, result: Result<()>) -> ! {
+    match result {
+        Ok(()) => process::exit(0),
+        Err(err) => {
+            eprintln!("{}: {:#}", name, err);
+            process::exit(exit_status(&err))
+        }
+    }
+}
+
+/// parse the command line, exiting with [EXIT_USAGE] if it is invalid
+///
+/// `--help` and `--version` still exit with status `0`.
+pub fn parse_args<'a>(app: clap::App<'a, '_>) -> clap::ArgMatches<'a> {
+    app.get_matches_safe().unwrap_or_else(|err| {
+        if !err.use_stderr() {
+            err.exit();
+        }
+        eprintln!("{}", err.message);
+        process::exit(EXIT_USAGE)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use anyhow::{anyhow, Context};
+    use uclcli::UclErrorKind;
+
+    use super::{exit_status, DecompressionFailed, Failure, EXIT_CORRUPT, EXIT_IO, EXIT_USAGE};
+
+    fn status(kind: UclErrorKind) -> i32 {
+        exit_status(
+            &Err::<(), _>(kind)
+                .context("compression failed")
+                .unwrap_err(),
+        )
+    }
+
+    fn decoding_status(kind: UclErrorKind) -> i32 {
+        exit_status(&Err::<(), _>(kind).context(DecompressionFailed).unwrap_err())
+    }
+
+    #[test]
+    fn exit_status_mapping() {
+        assert_eq!(status(UclErrorKind::InputOverrun), EXIT_CORRUPT);
+        assert_eq!(status(UclErrorKind::ChecksumMismatch), EXIT_CORRUPT);
+        assert_eq!(status(UclErrorKind::SrcTooLarge), EXIT_USAGE);
+        assert_eq!(
+            status(UclErrorKind::DstTooSmall { needed: 2, got: 1 }),
+            EXIT_USAGE
+        );
+        assert_eq!(status(UclErrorKind::InvalidArgument), EXIT_USAGE);
+        assert_eq!(status(UclErrorKind::OutOfMemory), EXIT_IO);
+        assert_eq!(status(UclErrorKind::Io(io::ErrorKind::NotFound)), EXIT_IO);
+        assert_eq!(
+            status(UclErrorKind::Io(io::ErrorKind::InvalidData)),
+            EXIT_CORRUPT
+        );
+
+        assert_eq!(decoding_status(UclErrorKind::InputOverrun), EXIT_CORRUPT);
+        assert_eq!(decoding_status(UclErrorKind::InvalidArgument), EXIT_CORRUPT);
+        assert_eq!(decoding_status(UclErrorKind::DstTooLarge), EXIT_CORRUPT);
+        assert_eq!(decoding_status(UclErrorKind::OutOfMemory), EXIT_IO);
+    }
+
+    #[test]
+    fn exit_status_of_other_errors() {
+        let err = io::Error::from(UclErrorKind::OutputOverrun);
+        assert_eq!(exit_status(&err.into()), EXIT_CORRUPT);
+        let err = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert_eq!(exit_status(&anyhow::Error::new(err).context("x")), EXIT_IO);
+        assert_eq!(exit_status(&Failure::usage("bad").into()), EXIT_USAGE);
+        assert_eq!(exit_status(&anyhow!("something else")), EXIT_IO);
+    }
+}
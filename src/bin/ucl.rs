@@ -19,68 +19,388 @@
 #[macro_use]
 extern crate clap;
 
-use std::fs::OpenOptions;
+mod common;
+
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
 use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::Instant;
 
-use anyhow::{Context, Result};
-use memmap::MmapMut;
+use anyhow::{anyhow, ensure, Context, Result};
+use memmap::{Mmap, MmapMut};
 
-use uclcli::{compress, compress_into_buffer, minimum_compression_buffer_size, ucl_init};
+use common::{
+    exit, install_signal_handler, parse_args, walk_files, write_output, Counting,
+    DecompressionFailed, Failure, OutputFile, Summary,
+};
 
-fn main() -> Result<()> {
-    let matches = clap_app!(ucl =>
-        (version: "0.1")
-        (author: "Kjell Braden <kjell.braden@bmw.de>")
-        (about: "libucl (NRV) compressor")
-        (@arg INPUT: -i --input [FILE] "Sets the input file to use [defaults to stdin]")
-        (@arg OUTPUT: -o --output [FILE] "Sets the output file to use [defaults to stdout]")
-    )
-    .get_matches();
+use uclcli::frame::{compress_stream, compress_stream_with_checksum, decompress_stream};
+use uclcli::{
+    compress_into_buffer_with_level, compress_with_level, decompress, library_version_string,
+    minimum_compression_buffer_size, ucl_init, CompressStats, DEFAULT_LEVEL, MAX_LEVEL, MIN_LEVEL,
+};
 
-    ucl_init();
+/// suffix appended to the input file name if no output file is given
+const SUFFIX: &str = ".ucl";
 
-    let mut input: Box<dyn Read> = match matches.value_of("INPUT") {
-        Some(path) => Box::new(
-            OpenOptions::new()
-                .read(true)
-                .open(&path)
-                .context("could not open input file")?,
-        ),
-        None => Box::new(io::stdin()),
-    };
+/// decompress `compressed` and make sure it matches `original`
+fn verify(original: &[u8], compressed: &[u8]) -> Result<()> {
+    let capacity = original
+        .len()
+        .try_into()
+        .context("input too large to verify")?;
+    let roundtrip = decompress(compressed, capacity)
+        .context("verification failed, compressed output cannot be decompressed")?;
+    ensure!(
+        roundtrip == original,
+        "verification failed, decompressed output does not match input"
+    );
+    Ok(())
+}
+
+/// a [Write] sink comparing everything written to it against the expected data
+struct Compare<'a> {
+    expected: &'a [u8],
+}
+
+impl Write for Compare<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.expected.starts_with(buf) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "data mismatch"));
+        }
+        self.expected = &self.expected[buf.len()..];
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
 
-    let mut inbuffer = Vec::new();
-    input.read_to_end(&mut inbuffer)?;
+/// decompress the framed stream `compressed` and make sure it matches `original`
+fn verify_stream(original: &[u8], compressed: &[u8]) -> Result<()> {
+    let mut compare = Compare { expected: original };
+    decompress_stream(&mut &compressed[..], &mut compare)
+        .context("verification failed, decompressed output does not match input")?;
+    ensure!(
+        compare.expected.is_empty(),
+        "verification failed, decompressed output does not match input"
+    );
+    Ok(())
+}
+
+/// compress `src` into a framed stream written to `dst`, appending a checksum if `checksum` is
+/// set
+fn compress_framed<R: Read, W: Write>(
+    src: &mut R,
+    dst: &mut W,
+    level: u8,
+    checksum: bool,
+) -> io::Result<u64> {
+    if checksum {
+        compress_stream_with_checksum(src, dst, level)
+    } else {
+        compress_stream(src, dst, level)
+    }
+}
 
+/// compress `inbuffer` into a single raw nrv2b stream without any framing, returning the
+/// compressed size
+fn compress_raw(
+    inbuffer: &[u8],
+    output_filename: Option<&str>,
+    level: u8,
+    verify_output: bool,
+    force: bool,
+) -> Result<usize> {
     let out_size = minimum_compression_buffer_size(inbuffer.len());
 
-    let output_filename = matches.value_of("OUTPUT");
-    match output_filename {
+    let numbytes = match output_filename {
         Some(path) => {
-            let file = OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .open(&path)
-                .context("could not create output file")?;
+            let out = OutputFile::create(path, force)?;
+            let file = out.file();
             file.set_len(out_size as u64)
                 .context("could not resize output file")?;
 
             let numbytes = unsafe {
-                let mut mmap = MmapMut::map_mut(&file).context("failed to map output file")?;
-                let nb =
-                    compress_into_buffer(&inbuffer, &mut mmap).context("decompression failed")?;
+                let mut mmap = MmapMut::map_mut(file).context("failed to map output file")?;
+                let nb = compress_into_buffer_with_level(inbuffer, &mut mmap, level)
+                    .context("compression failed")?;
+                if verify_output {
+                    verify(inbuffer, &mmap[..nb as usize])?;
+                }
                 mmap.flush().context("failed to write output")?;
                 nb
             };
             file.set_len(numbytes.into())
                 .context("failed to truncate output file")?;
+            out.persist()?;
+            numbytes as usize
         }
         None => {
-            let dst = compress(&inbuffer).context("decompression failed")?;
+            let dst = compress_with_level(inbuffer, level).context("compression failed")?;
+            if verify_output {
+                verify(inbuffer, &dst)?;
+            }
             io::stdout().write_all(&dst)?;
+            dst.len()
+        }
+    };
+
+    Ok(numbytes)
+}
+
+/// settings applied to every file compressed in one run
+struct Options {
+    level: u8,
+    verify: bool,
+    raw: bool,
+    checksum: bool,
+    force: bool,
+    remove: bool,
+    verbose: bool,
+    quiet: bool,
+}
+
+/// map `file` into memory if it is a non-empty regular file
+///
+/// Returns `None` for anything else, e.g. pipes, which have to be read instead.
+fn map_input(file: &File) -> Result<Option<Mmap>> {
+    let metadata = file
+        .metadata()
+        .context("could not read input file metadata")?;
+    if !metadata.is_file() || metadata.len() == 0 {
+        return Ok(None);
+    }
+    let mmap = unsafe { Mmap::map(file) }.context("failed to map input file")?;
+    Ok(Some(mmap))
+}
+
+/// compress `input_filename` into `output_filename`, using stdin and stdout if not given
+fn compress_file(
+    input_filename: Option<&str>,
+    output_filename: Option<&str>,
+    opts: &Options,
+) -> Result<CompressStats> {
+    let file = match input_filename {
+        Some(path) => Some(
+            OpenOptions::new()
+                .read(true)
+                .open(path)
+                .context("could not open input file")?,
+        ),
+        None => None,
+    };
+    let input: Box<dyn Read + '_> = match &file {
+        Some(file) => Box::new(file),
+        None => Box::new(io::stdin()),
+    };
+
+    let mut input = Counting::new(input);
+
+    let stats = if !opts.raw && !opts.verify {
+        let mut numbytes = 0;
+        write_output(output_filename, opts.force, |output| {
+            let mut output = Counting::new(output);
+            compress_framed(&mut input, &mut output, opts.level, opts.checksum)
+                .context("compression failed")?;
+            numbytes = output.count;
+            Ok(())
+        })?;
+        CompressStats::new(input.count as usize, numbytes as usize)
+    } else {
+        let mapped = match &file {
+            Some(file) => map_input(file)?,
+            None => None,
+        };
+        let mut buffer = Vec::new();
+        let inbuffer: &[u8] = match &mapped {
+            Some(mmap) => mmap,
+            None => {
+                input.read_to_end(&mut buffer)?;
+                &buffer
+            }
+        };
+
+        if opts.raw {
+            let numbytes = compress_raw(
+                inbuffer,
+                output_filename,
+                opts.level,
+                opts.verify,
+                opts.force,
+            )?;
+            CompressStats::new(inbuffer.len(), numbytes)
+        } else {
+            let mut dst = Vec::new();
+            compress_framed(&mut &inbuffer[..], &mut dst, opts.level, opts.checksum)
+                .context("compression failed")?;
+            verify_stream(inbuffer, &dst)?;
+
+            write_output(output_filename, opts.force, |output| {
+                output.write_all(&dst).context("failed to write output")
+            })?;
+            CompressStats::new(inbuffer.len(), dst.len())
+        }
+    };
+
+    if let (true, Some(path), Some(_)) = (opts.remove, input_filename, output_filename) {
+        drop(input);
+        drop(file);
+        fs::remove_file(path).context("could not remove input file")?;
+    }
+
+    if opts.verbose {
+        eprintln!("{}: {}", input_filename.unwrap_or("<stdin>"), stats);
+    }
+    Ok(stats)
+}
+
+/// compress `path` at every level and print a report of sizes and timings to stdout, without
+/// writing any output file
+fn benchmark(path: &str) -> Result<()> {
+    let inbuffer = fs::read(path).context("could not read input file")?;
+    let capacity = inbuffer
+        .len()
+        .try_into()
+        .context("input too large to benchmark, must be below 4GB")?;
+
+    println!(
+        "{:>5} {:>12} {:>7} {:>12} {:>12}",
+        "level", "size", "ratio", "compress", "decompress"
+    );
+    for level in MIN_LEVEL..=MAX_LEVEL {
+        let start = Instant::now();
+        let compressed = compress_with_level(&inbuffer, level).context("compression failed")?;
+        let compress_time = start.elapsed();
+
+        let start = Instant::now();
+        let roundtrip = decompress(&compressed, capacity).context(DecompressionFailed)?;
+        let decompress_time = start.elapsed();
+        ensure!(
+            roundtrip == inbuffer,
+            "level {}: decompressed output does not match input",
+            level
+        );
+
+        let stats = CompressStats::new(inbuffer.len(), compressed.len());
+        println!(
+            "{:>5} {:>12} {:>6.1}% {:>10.1}ms {:>10.1}ms",
+            level,
+            stats.output_len,
+            stats.ratio * 100.0,
+            compress_time.as_secs_f64() * 1000.0,
+            decompress_time.as_secs_f64() * 1000.0
+        );
+    }
+    Ok(())
+}
+
+/// compress every file below `dir` to `<name>.ucl`, skipping files which already are
+fn compress_recursive(dir: &str, opts: &Options) -> Result<()> {
+    let mut summary = Summary::default();
+    for path in walk_files(Path::new(dir))? {
+        let input = match path.to_str() {
+            Some(input) if input.ends_with(SUFFIX) => continue,
+            Some(input) => input,
+            None => {
+                summary.fail(&path, anyhow!("file name is not valid UTF-8"));
+                continue;
+            }
+        };
+        let output = format!("{}{}", input, SUFFIX);
+
+        match compress_file(Some(input), Some(&output), opts) {
+            Ok(stats) => summary.add(stats.input_len as u64, stats.output_len as u64),
+            Err(e) => summary.fail(&path, e),
         }
     }
+    summary.finish(opts.quiet)
+}
+
+fn run() -> Result<()> {
+    let version = format!("{} (libucl {})", crate_version!(), library_version_string());
+    let matches = parse_args(clap_app!(ucl =>
+        (version: version.as_str())
+        (author: "Kjell Braden <kjell.braden@bmw.de>")
+        (about: "libucl (NRV) compressor")
+        (@arg INPUT: -i --input [FILE] "Sets the input file to use [defaults to stdin]")
+        (@arg OUTPUT: -o --output [FILE] "Sets the output file to use [defaults to INPUT.ucl, or stdout if reading from stdin]")
+        (@arg stdout: -c --stdout conflicts_with[OUTPUT] "Writes to stdout, even if an input file is given")
+        (@arg recursive: -r --recursive requires[INPUT] conflicts_with[OUTPUT stdout] "Compresses every file in the INPUT directory and its subdirectories, skipping symlinks and .ucl files")
+        (@arg keep: -k --keep "Keeps the input file after successful compression [default]")
+        (@arg force: -f --force "Overwrites the output file if it already exists")
+        (@arg rm: --rm conflicts_with[keep] "Removes the input file after successful compression to an output file")
+        (@arg level: -l --level [LEVEL] "Sets the compression level, from 1 (fastest) to 10 (best) [defaults to 6]")
+        (@arg threads: -T --threads [N] "Sets the number of threads compressing blocks in parallel, 1 compresses sequentially [defaults to the number of CPUs]")
+        (@arg verbose: -v --verbose "Prints the input and output sizes and the compression ratio to stderr")
+        (@arg quiet: -q --quiet conflicts_with[verbose] "Prints nothing but errors, in particular no totals in recursive mode")
+        (@arg verify: --verify "Decompresses the result and compares it to the input before writing")
+        (@arg benchmark: --benchmark [FILE] conflicts_with[INPUT OUTPUT stdout recursive level verify raw rm] "Compresses FILE at every level and prints the size, ratio and compression and decompression times, without writing any output")
+        (@arg check: --check conflicts_with[raw] "Stores a CRC-32 of the input, which unucl verifies after decompressing")
+        (@arg raw: --raw "Writes a single raw nrv2b stream without framing, as understood by other libucl tools. Limits the input to 4GB")
+    ));
+
+    install_signal_handler();
+    ucl_init();
+
+    if let Some(path) = matches.value_of("benchmark") {
+        return benchmark(path);
+    }
+
+    let level = matches
+        .value_of("level")
+        .map(|x| {
+            x.parse::<u8>()
+                .map_err(|e| Failure::usage(format!("failed to parse --level: {}", e)))
+        })
+        .unwrap_or(Ok(DEFAULT_LEVEL))?;
+    ensure!(
+        (MIN_LEVEL..=MAX_LEVEL).contains(&level),
+        Failure::usage(format!(
+            "--level must be between {} and {}",
+            MIN_LEVEL, MAX_LEVEL
+        ))
+    );
 
+    if let Some(threads) = matches.value_of("threads") {
+        let threads = threads
+            .parse::<usize>()
+            .map_err(|e| Failure::usage(format!("failed to parse --threads: {}", e)))?;
+        ensure!(threads > 0, Failure::usage("--threads must be at least 1"));
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .context("failed to start compression threads")?;
+    }
+
+    let opts = Options {
+        level,
+        verify: matches.is_present("verify"),
+        raw: matches.is_present("raw"),
+        checksum: matches.is_present("check"),
+        force: matches.is_present("force"),
+        remove: matches.is_present("rm"),
+        verbose: matches.is_present("verbose"),
+        quiet: matches.is_present("quiet"),
+    };
+
+    let input_filename = matches.value_of("INPUT");
+    if matches.is_present("recursive") {
+        return compress_recursive(input_filename.unwrap(), &opts);
+    }
+
+    let output_filename = match (matches.is_present("stdout"), matches.value_of("OUTPUT")) {
+        (true, _) => None,
+        (false, Some(path)) => Some(path.to_owned()),
+        (false, None) => input_filename.map(|path| format!("{}{}", path, SUFFIX)),
+    };
+
+    compress_file(input_filename, output_filename.as_deref(), &opts)?;
     Ok(())
 }
+
+fn main() {
+    exit("ucl", run())
+}
@@ -19,73 +19,456 @@
 #[macro_use]
 extern crate clap;
 
-use std::fs::OpenOptions;
-use std::io::{self, Read, Write};
+mod common;
 
-use anyhow::{Context, Result};
+use std::convert::TryInto;
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
 use memmap::MmapMut;
 
-use uclcli::{decompress, decompress_into_buffer, ucl_init};
+use common::{
+    exit, exit_status, install_signal_handler, parse_args, walk_files, write_output, Counting,
+    DecompressionFailed, Failure, OutputFile, Summary,
+};
 
-const DEFAULT_BUFFER_SIZE: u32 = 512 * 1024 * 1024;
-fn main() -> Result<()> {
-    let matches = clap_app!(unucl =>
-        (version: "0.1")
-        (author: "Kjell Braden <kjell.braden@bmw.de>")
-        (about: "libucl (NRV) decompressor")
-        (@arg INPUT: -i --input [FILE] "Sets the input file to use [defaults to stdin]")
-        (@arg OUTPUT: -o --output [FILE] "Sets the output file to use [defaults to stdout]")
-        (@arg bufsize: -b --buffersize [SIZE] "Sets the decompression buffer size - set this if you know how much data to expect after decompression [defaults to 512MB]")
-    )
-    .get_matches();
+use uclcli::frame::{
+    decompress_stream, method_name, stream_info, Header, HEADER_LEN, MAGIC, STREAM_MAGIC,
+};
+use uclcli::{
+    decompress_auto, decompress_framed, decompress_into_buffer, estimate_decompressed_size,
+    library_version_string, ucl_init, CompressStats, UclErrorKind,
+};
 
-    ucl_init();
+/// initial decompression buffer size for raw input, relative to the compressed size
+const DEFAULT_EXPANSION: usize = 4;
+/// suffix stripped from the input file name if no output file is given
+const SUFFIX: &str = ".ucl";
 
-    let buffer_size = matches
-        .value_of("bufsize")
-        .map(|x| x.parse::<u32>().context("failed to parse --buffersize"))
-        .unwrap_or(Ok(DEFAULT_BUFFER_SIZE))?;
+/// decompress `src` into a memory map of `file`, starting with `initial_size` and growing the
+/// file until the decompressed data fits
+fn decompress_to_file(file: &File, src: &[u8], initial_size: u32) -> Result<u32> {
+    let mut size = initial_size.max(1);
+    loop {
+        file.set_len(size.into())
+            .context("could not resize output file")?;
 
-    let mut input: Box<dyn Read> = match matches.value_of("INPUT") {
-        Some(path) => Box::new(
+        let mut mmap = unsafe { MmapMut::map_mut(file) }.context("failed to map output file")?;
+        match decompress_into_buffer(src, &mut mmap) {
+            Ok(nb) => {
+                mmap.flush().context("failed to write output")?;
+                return Ok(nb);
+            }
+            Err(UclErrorKind::OutputOverrun) if size < u32::MAX => {
+                size = size.saturating_mul(2);
+            }
+            Err(UclErrorKind::OutputOverrun) => {
+                return Err(UclErrorKind::OutputOverrun)
+                    .context("decompression failed, output does not fit into 4 GiB");
+            }
+            Err(e) => return Err(e).context(DecompressionFailed),
+        }
+    }
+}
+
+/// decompress the framed stream read from `input` block by block, so neither the input nor
+/// the output ever has to be held in memory as a whole
+///
+/// Returns the decompressed size.
+fn decompress_framed_stream<R: Read>(
+    input: &mut R,
+    output_filename: Option<&str>,
+    force: bool,
+) -> Result<u64> {
+    let mut numbytes = 0;
+    write_output(output_filename, force, |mut output| {
+        numbytes = decompress_stream(input, &mut output).context(DecompressionFailed)?;
+        Ok(())
+    })?;
+    Ok(numbytes)
+}
+
+/// decompress the raw nrv2b stream in `inbuffer`, returning the decompressed size
+fn decompress_raw(
+    inbuffer: &[u8],
+    output_filename: Option<&str>,
+    buffer_size: u32,
+    force: bool,
+) -> Result<u64> {
+    let numbytes = match output_filename {
+        Some(path) => {
+            let out = OutputFile::create(path, force)?;
+            let numbytes = decompress_to_file(out.file(), inbuffer, buffer_size)?;
+            out.file()
+                .set_len(numbytes.into())
+                .context("failed to truncate output file")?;
+            out.persist()?;
+            numbytes.into()
+        }
+        None => {
+            let dst = decompress_auto(inbuffer, buffer_size).context(DecompressionFailed)?;
+            io::stdout().write_all(&dst)?;
+            dst.len() as u64
+        }
+    };
+
+    Ok(numbytes)
+}
+
+/// decompress the framed buffer in `inbuffer`, whose header records the decompressed size
+///
+/// Returns the decompressed size.
+fn decompress_framed_buffer(
+    inbuffer: &[u8],
+    output_filename: Option<&str>,
+    force: bool,
+) -> Result<u64> {
+    let dst = decompress_framed(inbuffer).context(DecompressionFailed)?;
+    write_output(output_filename, force, |output| {
+        output.write_all(&dst).context("failed to write output")
+    })?;
+    Ok(dst.len() as u64)
+}
+
+/// add a hint to errors caused by data following the end of the compressed stream, which
+/// usually means that several files were concatenated by mistake
+fn explain_error(err: anyhow::Error) -> anyhow::Error {
+    if is_trailing_data(&err) {
+        err.context("input continues after the end of the compressed data, were several files concatenated?")
+    } else {
+        err
+    }
+}
+
+/// whether `err` was caused by data following the end of the compressed stream
+fn is_trailing_data(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        let kind = match cause.downcast_ref::<io::Error>() {
+            Some(e) => e.get_ref().and_then(|e| e.downcast_ref::<UclErrorKind>()),
+            None => cause.downcast_ref::<UclErrorKind>(),
+        };
+        kind == Some(&UclErrorKind::InputNotConsumed)
+    })
+}
+
+/// settings applied to every file decompressed in one run
+struct Options {
+    raw: bool,
+    buffer_size: Option<u32>,
+    force: bool,
+    remove: bool,
+    verbose: bool,
+    quiet: bool,
+}
+
+/// format of an input file, as detected from its header
+enum Format {
+    /// a framed stream of blocks, see [decompress_stream]
+    Stream,
+    /// a single framed buffer, see [decompress_framed]
+    Framed,
+    /// a raw nrv2b stream without any header
+    Raw,
+}
+
+/// open `input_filename`, or stdin if not given, and detect its format
+///
+/// The returned reader still yields the complete input, including the header.
+fn open_input(input_filename: Option<&str>, raw: bool) -> Result<(Format, Box<dyn Read>)> {
+    let mut input: Box<dyn Read> = match input_filename {
+        Some(path) => Box::new(BufReader::new(
             OpenOptions::new()
                 .read(true)
-                .open(&path)
+                .open(path)
                 .context("could not open input file")?,
-        ),
+        )),
         None => Box::new(io::stdin()),
     };
+    if raw {
+        return Ok((Format::Raw, input));
+    }
+
+    let mut header = Vec::with_capacity(STREAM_MAGIC.len());
+    input
+        .by_ref()
+        .take(STREAM_MAGIC.len() as u64)
+        .read_to_end(&mut header)?;
+    let format = if header == STREAM_MAGIC {
+        Format::Stream
+    } else if header == MAGIC {
+        Format::Framed
+    } else {
+        let message = format!(
+            "{}: not in ucl format, use --raw to decompress a raw nrv2b stream",
+            input_filename.unwrap_or("<stdin>")
+        );
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message).into());
+    };
+    Ok((format, Box::new(io::Cursor::new(header).chain(input))))
+}
 
+/// read the rest of `input` into memory
+fn read_all(input: &mut dyn Read) -> Result<Vec<u8>> {
     let mut inbuffer = Vec::new();
-    input.read_to_end(&mut inbuffer)?;
+    input
+        .read_to_end(&mut inbuffer)
+        .context("could not read input file")?;
+    Ok(inbuffer)
+}
 
-    let output_filename = matches.value_of("OUTPUT");
-    match output_filename {
-        Some(path) => {
-            let file = OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .open(&path)
-                .context("could not create output file")?;
-            file.set_len(buffer_size.into())
-                .context("could not resize output file")?;
-
-            let numbytes = unsafe {
-                let mut mmap = MmapMut::map_mut(&file).context("failed to map output file")?;
-                let nb =
-                    decompress_into_buffer(&inbuffer, &mut mmap).context("decompression failed")?;
-                mmap.flush().context("failed to write output")?;
-                nb
-            };
-            file.set_len(numbytes.into())
-                .context("failed to truncate output file")?;
+/// initial decompression buffer size for the raw stream `inbuffer`
+///
+/// Unless given on the command line, this is the exact decompressed size if it can be
+/// determined from the stream, or a multiple of the compressed size otherwise.
+fn raw_buffer_size(inbuffer: &[u8], opts: &Options) -> u32 {
+    opts.buffer_size.unwrap_or_else(|| {
+        estimate_decompressed_size(inbuffer)
+            .unwrap_or_else(|| inbuffer.len().saturating_mul(DEFAULT_EXPANSION))
+            .try_into()
+            .unwrap_or(u32::MAX)
+    })
+}
+
+/// decompress `input_filename` without writing any output, returning the decompressed size
+fn test_file(input_filename: Option<&str>, opts: &Options) -> Result<u64> {
+    let (format, mut input) = open_input(input_filename, opts.raw)?;
+    let numbytes = match format {
+        Format::Stream => decompress_stream(&mut input, &mut io::sink())
+            .context(DecompressionFailed)
+            .map_err(explain_error)?,
+        Format::Framed => {
+            let inbuffer = read_all(&mut input)?;
+            decompress_framed(&inbuffer)
+                .context(DecompressionFailed)
+                .map_err(explain_error)?
+                .len() as u64
         }
-        None => {
-            let dst = decompress(&inbuffer, buffer_size).context("decompression failed")?;
-            io::stdout().write_all(&dst)?;
+        Format::Raw => {
+            let inbuffer = read_all(&mut input)?;
+            decompress_auto(&inbuffer, raw_buffer_size(&inbuffer, opts))
+                .context(DecompressionFailed)
+                .map_err(explain_error)?
+                .len() as u64
+        }
+    };
+    Ok(numbytes)
+}
+
+/// decompress `input_filename` into `output_filename`, using stdin and stdout if not given
+///
+/// Returns statistics for the compression reversed by this, i.e. with the decompressed size as
+/// input and the compressed size as output.
+fn decompress_file(
+    input_filename: Option<&str>,
+    output_filename: Option<&str>,
+    opts: &Options,
+) -> Result<CompressStats> {
+    let (format, input) = open_input(input_filename, opts.raw)?;
+    let mut input = Counting::new(input);
+
+    let result = match format {
+        Format::Stream => decompress_framed_stream(&mut input, output_filename, opts.force),
+        Format::Framed => {
+            let inbuffer = read_all(&mut input)?;
+            decompress_framed_buffer(&inbuffer, output_filename, opts.force)
+        }
+        Format::Raw => {
+            let inbuffer = read_all(&mut input)?;
+            let buffer_size = raw_buffer_size(&inbuffer, opts);
+            decompress_raw(&inbuffer, output_filename, buffer_size, opts.force)
         }
+    };
+    let numbytes = result.map_err(explain_error)?;
+    let stats = CompressStats::new(numbytes as usize, input.count as usize);
+    drop(input);
+
+    if let (true, Some(path), Some(_)) = (opts.remove, input_filename, output_filename) {
+        fs::remove_file(path).context("could not remove input file")?;
     }
 
+    if opts.verbose {
+        eprintln!("{}: {}", input_filename.unwrap_or("<stdin>"), stats);
+    }
+    Ok(stats)
+}
+
+/// describe the compression of `path` as recorded in its headers, without decompressing it
+///
+/// Files in an unknown format are described as such, as they may well be raw nrv2b streams.
+fn describe_file(path: &str) -> Result<String> {
+    let file = File::open(path).context("could not open input file")?;
+    let compressed_len = file
+        .metadata()
+        .context("could not read input file metadata")?
+        .len();
+    let mut input = BufReader::new(file);
+
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    input
+        .by_ref()
+        .take(HEADER_LEN as u64)
+        .read_to_end(&mut header)
+        .context("could not read input file")?;
+    let (method, level, original_len, checksum) = if header.starts_with(&MAGIC) {
+        let header = Header::parse(&header).context("invalid header")?;
+        let checksum = header.checksum.is_some();
+        (header.method, header.level, header.original_len, checksum)
+    } else if header.starts_with(&STREAM_MAGIC) {
+        let (header, original_len) =
+            stream_info(&mut io::Cursor::new(header).chain(input)).context("invalid stream")?;
+        (header.method, header.level, original_len, header.checksum)
+    } else {
+        return Ok(format!(
+            "{}: unknown format, raw nrv2b stream or not compressed",
+            path
+        ));
+    };
+
+    let mut description = format!("{}: {}", path, method_name(method).unwrap_or("unknown"));
+    if let Some(level) = level {
+        description += &format!(" level {}", level);
+    }
+    description += &format!(", original {} bytes", original_len);
+    if original_len > 0 {
+        let saved = 100.0 - compressed_len as f64 * 100.0 / original_len as f64;
+        description += &format!(", {:.0}% saved", saved);
+    }
+    if checksum {
+        description += ", checksummed";
+    }
+    Ok(description)
+}
+
+/// print a description of every file in `paths` to stdout, see [describe_file]
+fn list_files<'a>(paths: impl Iterator<Item = &'a str>) -> Result<()> {
+    let mut failed = 0;
+    let mut status = 0;
+    for path in paths {
+        match describe_file(path) {
+            Ok(description) => println!("{}", description),
+            Err(e) => {
+                eprintln!("{}: {:#}", path, e);
+                failed += 1;
+                status = status.max(exit_status(&e));
+            }
+        }
+    }
+    if failed > 0 {
+        bail!(Failure {
+            status,
+            message: format!("{} files failed", failed),
+        });
+    }
     Ok(())
 }
+
+/// decompress every `.ucl` file below `dir` next to it, stripping the suffix
+fn decompress_recursive(dir: &str, opts: &Options) -> Result<()> {
+    let mut summary = Summary::default();
+    for path in walk_files(Path::new(dir))? {
+        let (input, output) = match path.to_str() {
+            Some(input) => match input.strip_suffix(SUFFIX) {
+                // a file named just `.ucl` has no name to decompress to
+                Some(output) if path.file_name() != Some(OsStr::new(SUFFIX)) => (input, output),
+                _ => continue,
+            },
+            None => {
+                summary.fail(&path, anyhow!("file name is not valid UTF-8"));
+                continue;
+            }
+        };
+
+        match decompress_file(Some(input), Some(output), opts) {
+            Ok(stats) => summary.add(stats.input_len as u64, stats.output_len as u64),
+            Err(e) => summary.fail(&path, e),
+        }
+    }
+    summary.finish(opts.quiet)
+}
+
+fn run() -> Result<()> {
+    let version = format!("{} (libucl {})", crate_version!(), library_version_string());
+    let matches = parse_args(clap_app!(unucl =>
+        (version: version.as_str())
+        (author: "Kjell Braden <kjell.braden@bmw.de>")
+        (about: "libucl (NRV) decompressor")
+        (@arg INPUT: -i --input [FILE] "Sets the input file to use [defaults to stdin]")
+        (@arg OUTPUT: -o --output [FILE] "Sets the output file to use [defaults to INPUT without .ucl, or stdout if reading from stdin]")
+        (@arg stdout: -c --stdout conflicts_with[OUTPUT] "Writes to stdout, even if an input file is given")
+        (@arg recursive: -r --recursive requires[INPUT] conflicts_with[OUTPUT stdout test] "Decompresses every .ucl file in the INPUT directory and its subdirectories, skipping symlinks")
+        (@arg keep: -k --keep "Keeps the input file after successful decompression [default]")
+        (@arg force: -f --force "Overwrites the output file if it already exists")
+        (@arg rm: --rm conflicts_with[keep] "Removes the input file after successful decompression to an output file")
+        (@arg raw: --raw "Reads a single raw nrv2b stream without framing, as written by other libucl tools or ucl --raw")
+        (@arg bufsize: -b --buffersize [SIZE] requires[raw] "Sets the initial decompression buffer size for raw input, which is grown as needed - set this if you know how much data to expect after decompression [defaults to the decompressed size determined from the input]")
+        (@arg verbose: -v --verbose "Prints the compressed and decompressed sizes and the compression ratio to stderr")
+        (@arg quiet: -q --quiet conflicts_with[verbose] "Prints nothing but errors, in particular no totals in recursive mode and no result of --test")
+        (@arg test: -t --test conflicts_with[OUTPUT] "Tests the input for corruption without writing any output")
+        (@arg list: -l --list [FILE]... conflicts_with[INPUT OUTPUT stdout recursive test raw rm] "Prints the method, level and sizes recorded in the headers of every FILE, without decompressing them")
+    ));
+
+    install_signal_handler();
+    ucl_init();
+
+    if let Some(paths) = matches.values_of("list") {
+        return list_files(paths);
+    }
+
+    let buffer_size = match matches.value_of("bufsize") {
+        Some(x) => Some(
+            x.parse::<u32>()
+                .map_err(|e| Failure::usage(format!("failed to parse --buffersize: {}", e)))?,
+        ),
+        None => None,
+    };
+    let opts = Options {
+        raw: matches.is_present("raw"),
+        buffer_size,
+        force: matches.is_present("force"),
+        remove: matches.is_present("rm"),
+        verbose: matches.is_present("verbose"),
+        quiet: matches.is_present("quiet"),
+    };
+
+    let input_filename = matches.value_of("INPUT");
+    if matches.is_present("recursive") {
+        return decompress_recursive(input_filename.unwrap(), &opts);
+    }
+
+    if matches.is_present("test") {
+        let numbytes = test_file(input_filename, &opts)?;
+        if !opts.quiet {
+            eprintln!(
+                "{}: OK, {} bytes",
+                input_filename.unwrap_or("<stdin>"),
+                numbytes
+            );
+        }
+        return Ok(());
+    }
+
+    let output_filename = match (matches.is_present("stdout"), matches.value_of("OUTPUT")) {
+        (true, _) => None,
+        (false, Some(path)) => Some(path),
+        (false, None) => match input_filename {
+            Some(path) => match path.strip_suffix(SUFFIX) {
+                Some(stripped) if !stripped.is_empty() => Some(stripped),
+                _ => bail!(Failure::usage(format!(
+                    "{}: unknown suffix, use --output or --stdout to decompress anyway",
+                    path
+                ))),
+            },
+            None => None,
+        },
+    };
+
+    decompress_file(input_filename, output_filename, &opts)?;
+    Ok(())
+}
+
+fn main() {
+    exit("unucl", run())
+}
@@ -0,0 +1,83 @@
+/*
+ * uclcli bytes.rs - compression into bytes::Bytes
+ * Copyright (C) 2020-2021  BMW Group
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Compression and decompression into [Bytes], for code passing payloads around as such.
+//!
+//! The output is written directly into the uninitialized capacity of a [BytesMut], which is
+//! then frozen, so there is no copy and no zeroing of the output buffer.
+//!
+//! ```
+//! let compressed = uclcli::bytes::compress_bytes(&[0; 1024]).unwrap();
+//! let decompressed = uclcli::bytes::decompress_bytes(&compressed, 1024).unwrap();
+//! assert_eq!(decompressed, [0u8; 1024].as_ref());
+//! ```
+
+use ::bytes::{Bytes, BytesMut};
+
+use crate::{
+    compress_into_uninit, decompress_into_uninit, minimum_compression_buffer_size, UclErrorKind,
+};
+
+/// NRV compress a buffer into a newly allocated [Bytes], see [crate::compress].
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [crate::ucl_init].
+pub fn compress_bytes(src: &[u8]) -> core::result::Result<Bytes, UclErrorKind> {
+    let capacity = minimum_compression_buffer_size(src.len());
+    let mut dst = BytesMut::with_capacity(capacity);
+
+    let numbytes = compress_into_uninit(src, &mut dst.spare_capacity_mut()[..capacity])?;
+    // SAFETY: compression initialized the first `numbytes` bytes
+    unsafe { dst.set_len(numbytes as usize) };
+    Ok(dst.freeze())
+}
+
+/// decompress a NRV compressed buffer into a newly allocated [Bytes], see [crate::decompress].
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [crate::ucl_init].
+pub fn decompress_bytes(
+    src: &[u8],
+    dst_capacity: u32,
+) -> core::result::Result<Bytes, UclErrorKind> {
+    let capacity = dst_capacity as usize;
+    let mut dst = BytesMut::with_capacity(capacity);
+
+    let numbytes = decompress_into_uninit(src, &mut dst.spare_capacity_mut()[..capacity])?;
+    // SAFETY: decompression initialized the first `numbytes` bytes
+    unsafe { dst.set_len(numbytes as usize) };
+    Ok(dst.freeze())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compress_bytes, decompress_bytes};
+    use crate::{compress, UclErrorKind};
+
+    #[test]
+    fn bytes_roundtrip() {
+        let src: Vec<u8> = (0..10_000u32).map(|i| (i % 97) as u8).collect();
+        let compressed = compress_bytes(&src).unwrap();
+        assert_eq!(compressed, compress(&src).unwrap());
+        assert_eq!(decompress_bytes(&compressed, 10_000).unwrap(), src);
+        assert_eq!(
+            decompress_bytes(&compressed, 9_999),
+            Err(UclErrorKind::OutputOverrun)
+        );
+    }
+}
@@ -0,0 +1,253 @@
+/*
+ * uclcli capi.rs - C API
+ * Copyright (C) 2020-2021  BMW Group
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! C API for the checked wrappers in this crate.
+//!
+//! This lets C and C++ code use the same length checks and version-checked initialization as
+//! Rust code, instead of calling libucl directly. The declarations are in `include/uclcli.h`,
+//! which is generated with `cbindgen --config cbindgen.toml --output include/uclcli.h`.
+//!
+//! None of these functions panic: initialization failures are reported as
+//! [UclcliStatus::InitFailed] instead.
+
+use core::slice;
+
+use crate::{
+    compress_into_buffer, decompress_into_buffer, minimum_compression_buffer_size,
+    ucl_init_checked, UclErrorKind,
+};
+
+/// Status codes returned by the C API.
+///
+/// The values are stable, new codes are only ever added at the end.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UclcliStatus {
+    /// success
+    Ok = 0,
+    /// libucl cannot be initialized, e.g. because the linked version is incompatible
+    InitFailed = 1,
+    /// an unspecified libucl error
+    GenericError = 2,
+    /// an argument is invalid, e.g. a required pointer is null
+    InvalidArgument = 3,
+    /// libucl ran out of memory
+    OutOfMemory = 4,
+    /// the input cannot be compressed
+    NotCompressible = 5,
+    /// the compressed data is truncated
+    InputOverrun = 6,
+    /// the decompressed data does not fit into the destination buffer
+    OutputOverrun = 7,
+    /// the compressed data refers to data before the start of the output
+    LookbehindOverrun = 8,
+    /// the compressed data has no end marker
+    EofNotFound = 9,
+    /// the input continues after the end of the compressed data
+    InputNotConsumed = 10,
+    /// the compressed data overlaps the output too much for in-place decompression
+    OverlapOverrun = 11,
+    /// the source buffer is larger than 4 GiB
+    SrcTooLarge = 12,
+    /// the destination buffer is larger than 4 GiB
+    DstTooLarge = 13,
+    /// the destination buffer is too small, the required size is stored in the length argument
+    DstTooSmall = 14,
+    /// the decompressed data does not match its recorded checksum
+    ChecksumMismatch = 15,
+    /// any other error
+    Unknown = 255,
+}
+
+impl From<UclErrorKind> for UclcliStatus {
+    fn from(kind: UclErrorKind) -> Self {
+        match kind {
+            UclErrorKind::GenericError => UclcliStatus::GenericError,
+            UclErrorKind::InvalidArgument => UclcliStatus::InvalidArgument,
+            UclErrorKind::OutOfMemory => UclcliStatus::OutOfMemory,
+            UclErrorKind::NotCompressible => UclcliStatus::NotCompressible,
+            UclErrorKind::InputOverrun => UclcliStatus::InputOverrun,
+            UclErrorKind::OutputOverrun => UclcliStatus::OutputOverrun,
+            UclErrorKind::LookbehindOverrun => UclcliStatus::LookbehindOverrun,
+            UclErrorKind::EofNotFound => UclcliStatus::EofNotFound,
+            UclErrorKind::InputNotConsumed => UclcliStatus::InputNotConsumed,
+            UclErrorKind::OverlapOverrun => UclcliStatus::OverlapOverrun,
+            UclErrorKind::SrcTooLarge => UclcliStatus::SrcTooLarge,
+            UclErrorKind::DstTooLarge => UclcliStatus::DstTooLarge,
+            UclErrorKind::DstTooSmall { .. } => UclcliStatus::DstTooSmall,
+            UclErrorKind::ChecksumMismatch => UclcliStatus::ChecksumMismatch,
+            _ => UclcliStatus::Unknown,
+        }
+    }
+}
+
+/// Initialize libucl.
+///
+/// Calling this is optional, all other functions initialize libucl on first use. It is safe to
+/// call this any number of times from any number of threads.
+#[no_mangle]
+pub extern "C" fn uclcli_init() -> UclcliStatus {
+    match ucl_init_checked() {
+        Ok(()) => UclcliStatus::Ok,
+        Err(_) => UclcliStatus::InitFailed,
+    }
+}
+
+/// Size of the destination buffer required to compress `src_len` bytes.
+#[no_mangle]
+pub extern "C" fn uclcli_compress_bound(src_len: usize) -> usize {
+    minimum_compression_buffer_size(src_len)
+}
+
+/// build the slices for a call, or `None` if the pointers are invalid
+///
+/// SAFETY: see [uclcli_compress]
+unsafe fn buffers<'a>(
+    src: *const u8,
+    src_len: usize,
+    dst: *mut u8,
+    dst_len: *mut usize,
+) -> Option<(&'a [u8], &'a mut [u8])> {
+    if dst_len.is_null() || (src.is_null() && src_len > 0) || (dst.is_null() && *dst_len > 0) {
+        return None;
+    }
+    let src = match src_len {
+        0 => &[][..],
+        _ => slice::from_raw_parts(src, src_len),
+    };
+    let dst = match *dst_len {
+        0 => &mut [][..],
+        _ => slice::from_raw_parts_mut(dst, *dst_len),
+    };
+    Some((src, dst))
+}
+
+/// Compress `src_len` bytes at `src` into the buffer `dst` of `*dst_len` bytes.
+///
+/// On success, the compressed size is stored in `*dst_len`. If the buffer is too small,
+/// `UCLCLI_STATUS_DST_TOO_SMALL` is returned and the required size is stored in `*dst_len`, see
+/// also `uclcli_compress_bound`.
+///
+/// # Safety
+/// `src` must be valid for reads of `src_len` bytes and `dst` must be valid for writes of
+/// `*dst_len` bytes. Either may be null if its length is zero. `dst_len` must not be null.
+#[no_mangle]
+pub unsafe extern "C" fn uclcli_compress(
+    src: *const u8,
+    src_len: usize,
+    dst: *mut u8,
+    dst_len: *mut usize,
+) -> UclcliStatus {
+    if ucl_init_checked().is_err() {
+        return UclcliStatus::InitFailed;
+    }
+    let (src, dst) = match buffers(src, src_len, dst, dst_len) {
+        Some(v) => v,
+        None => return UclcliStatus::InvalidArgument,
+    };
+
+    match compress_into_buffer(src, dst) {
+        Ok(nb) => {
+            *dst_len = nb as usize;
+            UclcliStatus::Ok
+        }
+        Err(UclErrorKind::DstTooSmall { needed, .. }) => {
+            *dst_len = needed;
+            UclcliStatus::DstTooSmall
+        }
+        Err(e) => e.into(),
+    }
+}
+
+/// Decompress `src_len` bytes at `src` into the buffer `dst` of `*dst_len` bytes.
+///
+/// On success, the decompressed size is stored in `*dst_len`. If the buffer is too small,
+/// `UCLCLI_STATUS_OUTPUT_OVERRUN` is returned.
+///
+/// # Safety
+/// `src` must be valid for reads of `src_len` bytes and `dst` must be valid for writes of
+/// `*dst_len` bytes. Either may be null if its length is zero. `dst_len` must not be null.
+#[no_mangle]
+pub unsafe extern "C" fn uclcli_decompress(
+    src: *const u8,
+    src_len: usize,
+    dst: *mut u8,
+    dst_len: *mut usize,
+) -> UclcliStatus {
+    if ucl_init_checked().is_err() {
+        return UclcliStatus::InitFailed;
+    }
+    let (src, dst) = match buffers(src, src_len, dst, dst_len) {
+        Some(v) => v,
+        None => return UclcliStatus::InvalidArgument,
+    };
+
+    match decompress_into_buffer(src, dst) {
+        Ok(nb) => {
+            *dst_len = nb as usize;
+            UclcliStatus::Ok
+        }
+        Err(e) => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::ptr;
+
+    use super::{uclcli_compress, uclcli_compress_bound, uclcli_decompress, UclcliStatus};
+
+    #[test]
+    fn capi_roundtrip() {
+        let src = [0u8; 1024];
+        let mut compressed = vec![0u8; uclcli_compress_bound(src.len())];
+        let mut len = compressed.len();
+        let status =
+            unsafe { uclcli_compress(src.as_ptr(), src.len(), compressed.as_mut_ptr(), &mut len) };
+        assert_eq!(status, UclcliStatus::Ok);
+        assert_eq!(
+            &compressed[..len],
+            b"\x92\x00\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff"
+        );
+
+        let mut dst = [0xffu8; 1024];
+        let mut dst_len = dst.len();
+        let status =
+            unsafe { uclcli_decompress(compressed.as_ptr(), len, dst.as_mut_ptr(), &mut dst_len) };
+        assert_eq!(status, UclcliStatus::Ok);
+        assert_eq!(dst_len, 1024);
+        assert_eq!(dst, src);
+
+        let mut small = [0u8; 16];
+        let mut small_len = small.len();
+        let status =
+            unsafe { uclcli_compress(src.as_ptr(), src.len(), small.as_mut_ptr(), &mut small_len) };
+        assert_eq!(status, UclcliStatus::DstTooSmall);
+        assert_eq!(small_len, 1408);
+    }
+
+    #[test]
+    fn capi_null_pointers() {
+        let status = unsafe { uclcli_compress(ptr::null(), 4, ptr::null_mut(), ptr::null_mut()) };
+        assert_eq!(status, UclcliStatus::InvalidArgument);
+
+        let mut len = 16;
+        let status = unsafe { uclcli_decompress(ptr::null(), 0, ptr::null_mut(), &mut len) };
+        assert_eq!(status, UclcliStatus::InvalidArgument);
+    }
+}
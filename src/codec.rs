@@ -0,0 +1,146 @@
+/*
+ * uclcli codec.rs - codec abstraction over the NRV variants
+ * Copyright (C) 2020-2021  BMW Group
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A [Codec] trait for code that picks its compression algorithm at runtime.
+//!
+//! libucl implements three variants of the NRV algorithm, which produce incompatible streams:
+//! [Nrv2b], which is what the rest of this crate uses, [Nrv2d] and [Nrv2e]. Data must be
+//! decompressed with the variant it was compressed with.
+//!
+//! ```
+//! use uclcli::codec::{Codec, Nrv2b, Nrv2e};
+//!
+//! let algorithm = "nrv2e"; // e.g. from a config file
+//! let codec: Box<dyn Codec> = match algorithm {
+//!     "nrv2e" => Box::new(Nrv2e),
+//!     _ => Box::new(Nrv2b),
+//! };
+//! let compressed = codec.compress(&[0; 1024]).unwrap();
+//! assert_eq!(codec.decompress(&compressed, 1024).unwrap(), [0u8; 1024].as_ref());
+//! ```
+
+use alloc::vec::Vec;
+
+use crate::sys::{
+    ucl_nrv2b_99_compress, ucl_nrv2b_decompress_safe_8, ucl_nrv2d_99_compress,
+    ucl_nrv2d_decompress_safe_8, ucl_nrv2e_99_compress, ucl_nrv2e_decompress_safe_8,
+};
+use crate::{compress_using, decompress_using, UclErrorKind, DEFAULT_LEVEL};
+
+/// A compression algorithm.
+pub trait Codec {
+    /// compress a buffer into a newly allocated buffer, see [crate::compress]
+    fn compress(&self, src: &[u8]) -> core::result::Result<Vec<u8>, UclErrorKind>;
+
+    /// decompress a buffer into a newly allocated buffer of at most `dst_capacity` bytes, see
+    /// [crate::decompress]
+    fn decompress(
+        &self,
+        src: &[u8],
+        dst_capacity: u32,
+    ) -> core::result::Result<Vec<u8>, UclErrorKind>;
+}
+
+/// the NRV2B algorithm, as used by the rest of this crate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Nrv2b;
+
+/// the NRV2D algorithm
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Nrv2d;
+
+/// the NRV2E algorithm, which usually compresses best of the three
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Nrv2e;
+
+impl Codec for Nrv2b {
+    fn compress(&self, src: &[u8]) -> core::result::Result<Vec<u8>, UclErrorKind> {
+        compress_using(ucl_nrv2b_99_compress, src, DEFAULT_LEVEL)
+    }
+
+    fn decompress(
+        &self,
+        src: &[u8],
+        dst_capacity: u32,
+    ) -> core::result::Result<Vec<u8>, UclErrorKind> {
+        decompress_using(ucl_nrv2b_decompress_safe_8, src, dst_capacity)
+    }
+}
+
+impl Codec for Nrv2d {
+    fn compress(&self, src: &[u8]) -> core::result::Result<Vec<u8>, UclErrorKind> {
+        compress_using(ucl_nrv2d_99_compress, src, DEFAULT_LEVEL)
+    }
+
+    fn decompress(
+        &self,
+        src: &[u8],
+        dst_capacity: u32,
+    ) -> core::result::Result<Vec<u8>, UclErrorKind> {
+        decompress_using(ucl_nrv2d_decompress_safe_8, src, dst_capacity)
+    }
+}
+
+impl Codec for Nrv2e {
+    fn compress(&self, src: &[u8]) -> core::result::Result<Vec<u8>, UclErrorKind> {
+        compress_using(ucl_nrv2e_99_compress, src, DEFAULT_LEVEL)
+    }
+
+    fn decompress(
+        &self,
+        src: &[u8],
+        dst_capacity: u32,
+    ) -> core::result::Result<Vec<u8>, UclErrorKind> {
+        decompress_using(ucl_nrv2e_decompress_safe_8, src, dst_capacity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Codec, Nrv2b, Nrv2d, Nrv2e};
+    use crate::compress;
+
+    fn codecs() -> [Box<dyn Codec>; 3] {
+        [Box::new(Nrv2b), Box::new(Nrv2d), Box::new(Nrv2e)]
+    }
+
+    #[test]
+    fn codec_roundtrip() {
+        let src: Vec<u8> = (0..10_000u32).map(|i| (i % 97) as u8).collect();
+        for codec in codecs().iter() {
+            let compressed = codec.compress(&src).unwrap();
+            assert_eq!(codec.decompress(&compressed, 10_000).unwrap(), src);
+        }
+    }
+
+    #[test]
+    fn nrv2b_codec_matches_compress() {
+        let src = b"hello hello hello hello";
+        assert_eq!(Nrv2b.compress(src).unwrap(), compress(src).unwrap());
+    }
+
+    #[test]
+    fn variants_are_incompatible() {
+        let compressed = Nrv2e.compress(&[0xa5; 1024]).unwrap();
+        assert_ne!(compressed, Nrv2b.compress(&[0xa5; 1024]).unwrap());
+        assert_ne!(
+            Nrv2b.decompress(&compressed, 1024).ok(),
+            Some(vec![0xa5; 1024])
+        );
+    }
+}
@@ -0,0 +1,886 @@
+/*
+ * uclcli frame.rs - self-describing container format for NRV compressed data
+ * Copyright (C) 2020-2021  BMW Group
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Small container formats around NRV compressed data.
+//!
+//! A framed buffer, as created by [compress_framed], consists of a header followed by the raw
+//! nrv2b stream:
+//!
+//! | offset | size | content                                      |
+//! |--------|------|----------------------------------------------|
+//! | 0      | 4    | magic bytes `UCL\x1a`                        |
+//! | 4      | 1    | format version, currently `2`                |
+//! | 5      | 1    | `0x2b` (nrv2b) or `0x00` (stored)            |
+//! | 6      | 1    | compression level, `0` if unknown            |
+//! | 7      | 1    | flags, `0x01` if a checksum is present       |
+//! | 8      | 8    | uncompressed length, little endian           |
+//! | 16     | 4    | CRC-32 of the uncompressed data, or `0`      |
+//! | 20     | ...  | compressed data                              |
+//!
+//! Data that nrv2b cannot make any smaller is stored verbatim, with method `0x00`, so a framed
+//! buffer is never larger than its uncompressed data plus the header.
+//!
+//! A framed stream, as created by [compress_stream], is not limited in size. It consists of a
+//! header followed by a block stream as described in [crate::io]:
+//!
+//! | offset | size | content                                      |
+//! |--------|------|----------------------------------------------|
+//! | 0      | 4    | magic bytes `UCS\x1a`                        |
+//! | 4      | 1    | format version, currently `2`                |
+//! | 5      | 1    | NRV method, currently always `0x2b` (nrv2b)  |
+//! | 6      | 1    | compression level, `0` if unknown            |
+//! | 7      | 1    | flags, `0x01` if a checksum is present       |
+//! | 8      | ...  | blocks                                       |
+//!
+//! The blocks are followed by an empty block header and, if a checksum is present, the CRC-32 of
+//! the uncompressed data, little endian.
+//!
+//! Version `1` of both formats lacks the level, flags and checksum fields, and version `1`
+//! streams end after the last block instead of at an empty block header. It is still accepted
+//! when reading, the level is reported as unknown then.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::convert::TryInto;
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+
+#[cfg(feature = "rayon")]
+use crate::compress_with_level;
+#[cfg(feature = "std")]
+use crate::io::{UclReader, UclWriter};
+use crate::{
+    compress_into_buffer_with_level, crc32, decompress_bounded, minimum_compression_buffer_size,
+    UclErrorKind, DEFAULT_LEVEL,
+};
+#[cfg(feature = "std")]
+use crate::{MAX_LEVEL, MIN_LEVEL};
+
+/// magic bytes at the start of every framed buffer
+pub const MAGIC: [u8; 4] = *b"UCL\x1a";
+/// format version written by this crate
+pub const VERSION: u8 = 2;
+/// format version without the level byte, still accepted when reading
+const VERSION_1: u8 = 1;
+/// method identifier for nrv2b
+pub const METHOD_NRV2B: u8 = 0x2b;
+/// method identifier for data stored without compression in a framed buffer
+pub const METHOD_STORED: u8 = 0x00;
+/// header flag indicating that a checksum of the uncompressed data is present
+pub const FLAG_CHECKSUM: u8 = 0x01;
+/// size of the header preceding the compressed data
+pub const HEADER_LEN: usize = 20;
+/// size of a version 1 header, see [VERSION_1]
+const HEADER_LEN_V1: usize = 14;
+/// magic bytes at the start of every framed stream
+pub const STREAM_MAGIC: [u8; 4] = *b"UCS\x1a";
+/// size of the header preceding the blocks of a framed stream
+pub const STREAM_HEADER_LEN: usize = 8;
+/// amount of uncompressed data per block in streams written by [compress_stream]
+///
+/// This is the same with and without the `rayon` feature, so the output does not depend on the
+/// number of threads. It is small enough that medium sized inputs are spread across all threads.
+pub const STREAM_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+
+/// name of the NRV method identified by `method`, as written in the frame headers
+pub fn method_name(method: u8) -> Option<&'static str> {
+    match method {
+        0x00 => Some("stored"),
+        0x2b => Some("nrv2b"),
+        0x2d => Some("nrv2d"),
+        0x2e => Some("nrv2e"),
+        _ => None,
+    }
+}
+
+/// decode the level byte of a header, where `0` means unknown
+fn parse_level(level: u8) -> Option<u8> {
+    Some(level).filter(|&l| l != 0)
+}
+
+/// Parsed representation of a frame header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    /// NRV method used to compress the payload
+    pub method: u8,
+    /// compression level used, if recorded
+    pub level: Option<u8>,
+    /// length of the data after decompression
+    pub original_len: u64,
+    /// CRC-32 of the data after decompression, if recorded
+    pub checksum: Option<u32>,
+}
+
+impl Header {
+    /// parse and validate a header from the start of `src`
+    ///
+    /// Returns `Err(UclErrorKind::InvalidArgument)` if `src` is too short, does not start with
+    /// [MAGIC] or uses an unknown version, method or flag.
+    pub fn parse(src: &[u8]) -> core::result::Result<Self, UclErrorKind> {
+        Self::parse_with_len(src).map(|(header, _)| header)
+    }
+
+    /// like [Header::parse], but also return the size of the header in `src`
+    fn parse_with_len(src: &[u8]) -> core::result::Result<(Self, usize), UclErrorKind> {
+        if src.len() < 6 || src[..4] != MAGIC || ![METHOD_NRV2B, METHOD_STORED].contains(&src[5]) {
+            return Err(UclErrorKind::InvalidArgument);
+        }
+        let method = src[5];
+        match src[4] {
+            VERSION if src.len() >= HEADER_LEN && src[7] & !FLAG_CHECKSUM == 0 => {
+                let checksum = u32::from_le_bytes(src[16..20].try_into().unwrap());
+                let header = Header {
+                    method,
+                    level: parse_level(src[6]),
+                    original_len: u64::from_le_bytes(src[8..16].try_into().unwrap()),
+                    checksum: Some(checksum).filter(|_| src[7] & FLAG_CHECKSUM != 0),
+                };
+                Ok((header, HEADER_LEN))
+            }
+            VERSION_1 if src.len() >= HEADER_LEN_V1 => {
+                let header = Header {
+                    method,
+                    level: None,
+                    original_len: u64::from_le_bytes(src[6..14].try_into().unwrap()),
+                    checksum: None,
+                };
+                Ok((header, HEADER_LEN_V1))
+            }
+            _ => Err(UclErrorKind::InvalidArgument),
+        }
+    }
+
+    /// serialize this header
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut buf = [0u8; HEADER_LEN];
+        buf[..4].copy_from_slice(&MAGIC);
+        buf[4] = VERSION;
+        buf[5] = self.method;
+        buf[6] = self.level.unwrap_or(0);
+        if let Some(checksum) = self.checksum {
+            buf[7] = FLAG_CHECKSUM;
+            buf[16..].copy_from_slice(&checksum.to_le_bytes());
+        }
+        buf[8..16].copy_from_slice(&self.original_len.to_le_bytes());
+        buf
+    }
+}
+
+/// Parsed representation of a stream header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamHeader {
+    /// NRV method used to compress the blocks
+    pub method: u8,
+    /// compression level used, if recorded
+    pub level: Option<u8>,
+    /// whether the blocks are followed by a CRC-32 of the uncompressed data
+    pub checksum: bool,
+}
+
+impl StreamHeader {
+    /// read and validate a stream header from `src`
+    ///
+    /// Reads exactly as many bytes as the header is long, so that `src` is positioned at the
+    /// first block afterwards. Returns an error of kind [InvalidData](io::ErrorKind::InvalidData)
+    /// if `src` does not start with [STREAM_MAGIC] or uses an unknown version, method or flag.
+    #[cfg(feature = "std")]
+    pub fn read<R: Read>(src: &mut R) -> io::Result<Self> {
+        Self::read_with_version(src).map(|(header, _)| header)
+    }
+
+    /// like [StreamHeader::read], but also return the format version
+    #[cfg(feature = "std")]
+    pub(crate) fn read_with_version<R: Read>(src: &mut R) -> io::Result<(Self, u8)> {
+        let mut header = [0u8; STREAM_HEADER_LEN];
+        src.read_exact(&mut header[..6])?;
+        if header[..4] != STREAM_MAGIC || header[5] != METHOD_NRV2B {
+            return Err(invalid_stream_header());
+        }
+        let (level, flags) = match header[4] {
+            VERSION => {
+                src.read_exact(&mut header[6..])?;
+                (parse_level(header[6]), header[7])
+            }
+            VERSION_1 => (None, 0),
+            _ => return Err(invalid_stream_header()),
+        };
+        if flags & !FLAG_CHECKSUM != 0 {
+            return Err(invalid_stream_header());
+        }
+        let stream_header = StreamHeader {
+            method: header[5],
+            level,
+            checksum: flags & FLAG_CHECKSUM != 0,
+        };
+        Ok((stream_header, header[4]))
+    }
+
+    /// serialize this header
+    pub fn to_bytes(&self) -> [u8; STREAM_HEADER_LEN] {
+        let mut buf = [0u8; STREAM_HEADER_LEN];
+        buf[..4].copy_from_slice(&STREAM_MAGIC);
+        buf[4] = VERSION;
+        buf[5] = self.method;
+        buf[6] = self.level.unwrap_or(0);
+        if self.checksum {
+            buf[7] = FLAG_CHECKSUM;
+        }
+        buf
+    }
+}
+
+#[cfg(feature = "std")]
+fn invalid_stream_header() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid stream header")
+}
+
+/// read the header of a framed stream and the headers of all its blocks from `src`
+///
+/// Returns the stream header and the total uncompressed length, without decompressing any
+/// block. Errors are reported like in [decompress_stream].
+#[cfg(feature = "std")]
+pub fn stream_info<R: Read>(src: &mut R) -> io::Result<(StreamHeader, u64)> {
+    let (header, version) = StreamHeader::read_with_version(src)?;
+    let mut original_len = 0;
+    loop {
+        let (ulen, clen) = match crate::io::read_block_header(src)? {
+            Some((0, 0)) => break,
+            Some(v) => v,
+            None if version == VERSION_1 => return Ok((header, original_len)),
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        };
+        let skipped = io::copy(&mut src.by_ref().take(clen as u64), &mut io::sink())?;
+        if skipped != clen as u64 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        original_len += ulen as u64;
+    }
+    if header.checksum {
+        src.read_exact(&mut [0; 4])?;
+    }
+    Ok((header, original_len))
+}
+
+/// NRV compress a buffer into a newly allocated, self-describing buffer.
+///
+/// The result can be decompressed with [decompress_framed] without knowing the original size.
+/// Data that nrv2b cannot make any smaller is stored verbatim, see [crate::maybe_compress].
+///
+/// ```
+/// let framed = uclcli::compress_framed(&[0xa5; 1024]).unwrap();
+/// assert_eq!(uclcli::decompress_framed(&framed).unwrap(), [0xa5; 1024]);
+/// ```
+pub fn compress_framed(src: &[u8]) -> core::result::Result<Vec<u8>, UclErrorKind> {
+    compress_framed_with_level(src, DEFAULT_LEVEL)
+}
+
+/// like [compress_framed], but use compression level `level` and record it in the header
+pub fn compress_framed_with_level(
+    src: &[u8],
+    level: u8,
+) -> core::result::Result<Vec<u8>, UclErrorKind> {
+    framed(src, level, false)
+}
+
+/// like [compress_framed_with_level], but also record a CRC-32 of `src` in the header, which
+/// [decompress_framed] verifies
+///
+/// ```
+/// let framed = uclcli::frame::compress_framed_with_checksum(&[0xa5; 1024], 9).unwrap();
+/// assert_eq!(uclcli::decompress_framed(&framed).unwrap(), [0xa5; 1024]);
+/// ```
+pub fn compress_framed_with_checksum(
+    src: &[u8],
+    level: u8,
+) -> core::result::Result<Vec<u8>, UclErrorKind> {
+    framed(src, level, true)
+}
+
+/// compress `src` into a framed buffer, storing it verbatim if it does not get any smaller
+fn framed(src: &[u8], level: u8, checksum: bool) -> core::result::Result<Vec<u8>, UclErrorKind> {
+    let mut dst = vec![0u8; HEADER_LEN + minimum_compression_buffer_size(src.len())];
+    let nb = compress_into_buffer_with_level(src, &mut dst[HEADER_LEN..], level)? as usize;
+    let (method, level) = if nb < src.len() {
+        dst.truncate(HEADER_LEN + nb);
+        (METHOD_NRV2B, Some(level))
+    } else {
+        dst.truncate(HEADER_LEN);
+        dst.extend_from_slice(src);
+        (METHOD_STORED, None)
+    };
+    let header = Header {
+        method,
+        level,
+        original_len: src.len() as u64,
+        checksum: if checksum { Some(crc32(0, src)) } else { None },
+    };
+    dst[..HEADER_LEN].copy_from_slice(&header.to_bytes());
+    Ok(dst)
+}
+
+/// decompress a buffer created by [compress_framed]
+///
+/// If the header is missing or unknown, or if the compressed data does not match the length
+/// recorded in the header, this will return `Err(UclErrorKind::InvalidArgument)`. If the header
+/// records a checksum and the decompressed data does not match it, this will return
+/// `Err(UclErrorKind::ChecksumMismatch)`. The output buffer is sized from the compressed data
+/// like in [decompress_bounded](crate::decompress_bounded), so a forged length in the header
+/// cannot cause a large allocation.
+pub fn decompress_framed(src: &[u8]) -> core::result::Result<Vec<u8>, UclErrorKind> {
+    let (header, header_len) = Header::parse_with_len(src)?;
+    let payload = &src[header_len..];
+    let dst = if header.method == METHOD_STORED {
+        match (payload.len() as u64).cmp(&header.original_len) {
+            Ordering::Less => return Err(UclErrorKind::InvalidArgument),
+            Ordering::Equal => payload.to_vec(),
+            Ordering::Greater => return Err(UclErrorKind::InputNotConsumed),
+        }
+    } else {
+        let max_size = match header.original_len.try_into() {
+            Ok(v) => v,
+            Err(_) => return Err(UclErrorKind::DstTooLarge),
+        };
+        // the header is untrusted, so only allocate what the payload actually decodes to
+        let dst = decompress_bounded(payload, max_size)?;
+        if dst.len() as u64 != header.original_len {
+            return Err(UclErrorKind::InvalidArgument);
+        }
+        dst
+    };
+
+    match header.checksum {
+        Some(checksum) if checksum != crc32(0, &dst) => Err(UclErrorKind::ChecksumMismatch),
+        _ => Ok(dst),
+    }
+}
+
+/// NRV compress everything read from `src` into a framed stream written to `dst`.
+///
+/// The input is split into blocks of [STREAM_BLOCK_SIZE] bytes which are compressed
+/// independently, so the input size is not limited by libucl's 32 bit lengths. With the `rayon`
+/// feature and more than one thread in the current rayon pool, the blocks are compressed in
+/// parallel, which gives the same output. Returns the number of uncompressed bytes read from `src`.
+///
+/// ```
+/// let mut compressed = Vec::new();
+/// uclcli::frame::compress_stream(&mut &b"hello world"[..], &mut compressed, 6).unwrap();
+///
+/// let mut decompressed = Vec::new();
+/// uclcli::frame::decompress_stream(&mut &compressed[..], &mut decompressed).unwrap();
+/// assert_eq!(decompressed, b"hello world");
+/// ```
+#[cfg(feature = "std")]
+pub fn compress_stream<R: Read, W: Write>(src: &mut R, dst: &mut W, level: u8) -> io::Result<u64> {
+    write_stream(src, dst, level, false)
+}
+
+/// like [compress_stream], but also append a CRC-32 of the uncompressed data, which
+/// [decompress_stream] verifies
+#[cfg(feature = "std")]
+pub fn compress_stream_with_checksum<R: Read, W: Write>(
+    src: &mut R,
+    dst: &mut W,
+    level: u8,
+) -> io::Result<u64> {
+    write_stream(src, dst, level, true)
+}
+
+/// write a stream header followed by the blocks read from `src`, the end of the blocks and, if
+/// `checksum` is set, the checksum trailer to `dst`
+#[cfg(feature = "std")]
+fn write_stream<R: Read, W: Write>(
+    src: &mut R,
+    dst: &mut W,
+    level: u8,
+    checksum: bool,
+) -> io::Result<u64> {
+    if !(MIN_LEVEL..=MAX_LEVEL).contains(&level) {
+        return Err(UclErrorKind::InvalidArgument.into());
+    }
+
+    let header = StreamHeader {
+        method: METHOD_NRV2B,
+        level: Some(level),
+        checksum,
+    };
+    dst.write_all(&header.to_bytes())?;
+    if !checksum {
+        return write_blocks(src, dst, level);
+    }
+
+    let mut src = Crc32::new(src);
+    let nb = write_blocks(&mut src, dst, level)?;
+    dst.write_all(&src.crc.to_le_bytes())?;
+    dst.flush()?;
+    Ok(nb)
+}
+
+/// compress `src` into blocks written to `dst`, in parallel if possible, followed by the end of
+/// the blocks
+#[cfg(feature = "std")]
+fn write_blocks<R: Read, W: Write>(src: &mut R, dst: &mut W, level: u8) -> io::Result<u64> {
+    #[cfg(feature = "rayon")]
+    if rayon::current_num_threads() > 1 {
+        return compress_blocks_parallel(src, dst, level);
+    }
+
+    let mut writer = UclWriter::with_block_size(dst, STREAM_BLOCK_SIZE);
+    writer.set_level(level);
+    let nb = io::copy(src, &mut writer)?;
+    writer.finish()?;
+    Ok(nb)
+}
+
+/// compress `src` into blocks of [STREAM_BLOCK_SIZE] bytes on the rayon thread pool and write
+/// them to `dst` in input order
+///
+/// Up to one block per thread is held in memory, together with its compressed counterpart.
+#[cfg(feature = "rayon")]
+fn compress_blocks_parallel<R: Read, W: Write>(
+    src: &mut R,
+    dst: &mut W,
+    level: u8,
+) -> io::Result<u64> {
+    use rayon::prelude::*;
+
+    // initialize up front instead of racing from the workers
+    crate::ensure_init();
+
+    let batch_len = rayon::current_num_threads().max(1);
+    let mut total = 0;
+    let mut eof = false;
+    while !eof {
+        let mut blocks = Vec::with_capacity(batch_len);
+        while !eof && blocks.len() < batch_len {
+            let mut block = Vec::new();
+            src.by_ref()
+                .take(STREAM_BLOCK_SIZE as u64)
+                .read_to_end(&mut block)?;
+            eof = block.len() < STREAM_BLOCK_SIZE;
+            if !block.is_empty() {
+                blocks.push(block);
+            }
+        }
+
+        let compressed: Vec<_> = blocks
+            .par_iter()
+            .map(|block| compress_with_level(block, level))
+            .collect();
+        for (block, compressed) in blocks.iter().zip(compressed) {
+            crate::io::write_block(dst, block.len(), &compressed?)?;
+            total += block.len() as u64;
+        }
+    }
+    crate::io::write_end(dst)?;
+    dst.flush()?;
+    Ok(total)
+}
+
+/// decompress a framed stream read from `src`, as created by [compress_stream], into `dst`
+///
+/// Returns the number of decompressed bytes written to `dst`. If the stream header is missing
+/// or unknown, a block is corrupt or the data does not match the checksum at the end of the
+/// stream, this returns an error of kind [InvalidData](io::ErrorKind::InvalidData). If the
+/// stream is cut off, even between two blocks, it returns one of kind
+/// [UnexpectedEof](io::ErrorKind::UnexpectedEof). As the checksum can only be verified at the
+/// end, `dst` has already received all data by then.
+#[cfg(feature = "std")]
+pub fn decompress_stream<R: Read, W: Write>(src: &mut R, dst: &mut W) -> io::Result<u64> {
+    let (header, version) = StreamHeader::read_with_version(src)?;
+    if version == VERSION_1 {
+        return io::copy(&mut UclReader::without_end(src), dst);
+    }
+    if !header.checksum {
+        return io::copy(&mut UclReader::new(src), dst);
+    }
+
+    let mut dst = Crc32::new(dst);
+    let mut reader = UclReader::new(&mut *src);
+    let nb = io::copy(&mut reader, &mut dst)?;
+    let mut checksum = [0u8; 4];
+    reader.into_inner().read_exact(&mut checksum)?;
+    if u32::from_le_bytes(checksum) != dst.crc {
+        return Err(UclErrorKind::ChecksumMismatch.into());
+    }
+    Ok(nb)
+}
+
+/// [Read] or [Write] adapter computing the CRC-32 of all data passing through it
+#[cfg(feature = "std")]
+struct Crc32<T> {
+    inner: T,
+    crc: u32,
+}
+
+#[cfg(feature = "std")]
+impl<T> Crc32<T> {
+    fn new(inner: T) -> Self {
+        Crc32 { inner, crc: 0 }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Read for Crc32<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.crc = crc32(self.crc, &buf[..n]);
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> Write for Crc32<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc = crc32(self.crc, &buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{
+        compress_framed, compress_framed_with_checksum, compress_framed_with_level,
+        compress_stream, compress_stream_with_checksum, decompress_framed, decompress_stream,
+        method_name, stream_info, Header, StreamHeader, HEADER_LEN, MAGIC, METHOD_NRV2B,
+        METHOD_STORED, STREAM_HEADER_LEN, STREAM_MAGIC,
+    };
+    use crate::io::BLOCK_HEADER_LEN;
+    use crate::UclErrorKind;
+
+    #[test]
+    fn framed_nothing() {
+        let framed = compress_framed(&[]).unwrap();
+        assert_eq!(&framed[..4], MAGIC);
+        assert_eq!(framed.len(), HEADER_LEN);
+        assert_eq!(decompress_framed(&framed).unwrap(), b"");
+    }
+
+    #[test]
+    fn framed_stores_incompressible() {
+        let src: Vec<u8> = (0..=255u8).collect();
+        let framed = compress_framed(&src).unwrap();
+        let header = Header::parse(&framed).unwrap();
+        assert_eq!(header.method, METHOD_STORED);
+        assert_eq!(header.level, None);
+        assert_eq!(&framed[HEADER_LEN..], &src[..]);
+        assert_eq!(decompress_framed(&framed).unwrap(), src);
+    }
+
+    #[test]
+    fn framed_stored_length_mismatch() {
+        let mut framed = compress_framed(b"hello").unwrap();
+        framed[8] = 6;
+        assert_eq!(
+            decompress_framed(&framed).unwrap_err(),
+            UclErrorKind::InvalidArgument
+        );
+        framed[8] = 4;
+        assert_eq!(
+            decompress_framed(&framed).unwrap_err(),
+            UclErrorKind::InputNotConsumed
+        );
+    }
+
+    #[test]
+    fn framed_roundtrip() {
+        let src: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+        let framed = compress_framed(&src).unwrap();
+        assert_eq!(Header::parse(&framed).unwrap().original_len, 10000);
+        assert_eq!(decompress_framed(&framed).unwrap(), src);
+    }
+
+    #[test]
+    fn framed_records_level() {
+        let framed = compress_framed_with_level(&[0; 1024], 9).unwrap();
+        let header = Header::parse(&framed).unwrap();
+        assert_eq!(header.level, Some(9));
+        assert_eq!(method_name(header.method), Some("nrv2b"));
+        assert_eq!(Header::parse(&header.to_bytes()).unwrap(), header);
+    }
+
+    #[test]
+    fn framed_version_1() {
+        let framed = compress_framed(b"hello").unwrap();
+        let mut v1 = framed[..6].to_vec();
+        v1[4] = 1;
+        v1.extend_from_slice(&framed[8..16]);
+        v1.extend_from_slice(&framed[HEADER_LEN..]);
+
+        let header = Header::parse(&v1).unwrap();
+        assert_eq!(header.level, None);
+        assert_eq!(header.original_len, 5);
+        assert_eq!(decompress_framed(&v1).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn framed_checksum() {
+        let framed = compress_framed_with_checksum(&[0xa5; 1024], 6).unwrap();
+        assert_eq!(
+            Header::parse(&framed).unwrap().checksum,
+            Some(crate::crc32(0, &[0xa5; 1024]))
+        );
+        assert_eq!(decompress_framed(&framed).unwrap(), [0xa5; 1024]);
+
+        let mut stored = compress_framed_with_checksum(b"hello", 6).unwrap();
+        stored[HEADER_LEN] = b'j';
+        assert_eq!(
+            decompress_framed(&stored).unwrap_err(),
+            UclErrorKind::ChecksumMismatch
+        );
+    }
+
+    #[test]
+    fn framed_unknown_flag() {
+        let mut framed = compress_framed(b"hello").unwrap();
+        framed[7] = 0x80;
+        assert_eq!(
+            decompress_framed(&framed).unwrap_err(),
+            UclErrorKind::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn framed_bad_magic() {
+        let mut framed = compress_framed(b"hello").unwrap();
+        framed[0] = b'X';
+        assert_eq!(
+            decompress_framed(&framed).unwrap_err(),
+            UclErrorKind::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn framed_bad_version() {
+        let mut framed = compress_framed(b"hello").unwrap();
+        framed[4] = 0xff;
+        assert_eq!(
+            decompress_framed(&framed).unwrap_err(),
+            UclErrorKind::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn framed_truncated_header() {
+        assert_eq!(
+            decompress_framed(&MAGIC).unwrap_err(),
+            UclErrorKind::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn framed_length_mismatch() {
+        let mut framed = compress_framed(&[0; 1024]).unwrap();
+        framed[9] = 3;
+        assert_eq!(
+            decompress_framed(&framed).unwrap_err(),
+            UclErrorKind::OutputOverrun
+        );
+        framed[9] = 5;
+        assert_eq!(
+            decompress_framed(&framed).unwrap_err(),
+            UclErrorKind::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn framed_forged_length() {
+        let mut framed = compress_framed(&[0; 1024]).unwrap();
+        framed[8..16].copy_from_slice(&u64::from(u32::MAX).to_le_bytes());
+        assert_eq!(
+            decompress_framed(&framed).unwrap_err(),
+            UclErrorKind::InvalidArgument
+        );
+        framed[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(
+            decompress_framed(&framed).unwrap_err(),
+            UclErrorKind::DstTooLarge
+        );
+    }
+
+    #[test]
+    fn framed_trailing_data() {
+        let mut framed = compress_framed(b"hello").unwrap();
+        framed.push(0);
+        assert_eq!(
+            decompress_framed(&framed).unwrap_err(),
+            UclErrorKind::InputNotConsumed
+        );
+    }
+
+    #[test]
+    fn stream_nothing() {
+        let mut compressed = Vec::new();
+        assert_eq!(
+            compress_stream(&mut io::empty(), &mut compressed, 6).unwrap(),
+            0
+        );
+        assert_eq!(compressed.len(), STREAM_HEADER_LEN + BLOCK_HEADER_LEN);
+        assert_eq!(&compressed[..4], STREAM_MAGIC);
+
+        let mut decompressed = Vec::new();
+        assert_eq!(
+            decompress_stream(&mut &compressed[..], &mut decompressed).unwrap(),
+            0
+        );
+        assert!(decompressed.is_empty());
+    }
+
+    #[test]
+    fn stream_roundtrip() {
+        let src: Vec<u8> = (0..100000u32).map(|i| (i % 251) as u8).collect();
+        let mut compressed = Vec::new();
+        assert_eq!(
+            compress_stream(&mut &src[..], &mut compressed, 9).unwrap(),
+            100000
+        );
+
+        let mut decompressed = Vec::new();
+        assert_eq!(
+            decompress_stream(&mut &compressed[..], &mut decompressed).unwrap(),
+            100000
+        );
+        assert_eq!(decompressed, src);
+    }
+
+    #[test]
+    fn stream_info_without_decompressing() {
+        let src: Vec<u8> = (0..100000u32).map(|i| (i % 251) as u8).collect();
+        let mut compressed = Vec::new();
+        compress_stream(&mut &src[..], &mut compressed, 9).unwrap();
+
+        let (header, original_len) = stream_info(&mut &compressed[..]).unwrap();
+        assert_eq!(
+            header,
+            StreamHeader {
+                method: METHOD_NRV2B,
+                level: Some(9),
+                checksum: false,
+            }
+        );
+        assert_eq!(original_len, 100000);
+
+        let err = stream_info(&mut &compressed[..compressed.len() - 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn stream_checksum() {
+        let src: Vec<u8> = (0..100000u32).map(|i| (i % 251) as u8).collect();
+        let mut compressed = Vec::new();
+        compress_stream_with_checksum(&mut &src[..], &mut compressed, 6).unwrap();
+
+        let mut decompressed = Vec::new();
+        decompress_stream(&mut &compressed[..], &mut decompressed).unwrap();
+        assert_eq!(decompressed, src);
+        assert_eq!(stream_info(&mut &compressed[..]).unwrap().1, 100000);
+
+        let len = compressed.len();
+        let err = decompress_stream(&mut &compressed[..len - 1], &mut io::sink()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        compressed[len - 1] ^= 1;
+        let err = decompress_stream(&mut &compressed[..], &mut io::sink()).unwrap_err();
+        let kind: UclErrorKind = err.into();
+        assert_eq!(kind, UclErrorKind::ChecksumMismatch);
+    }
+
+    #[test]
+    fn stream_truncated_at_block_boundary() {
+        let src: Vec<u8> = (0..100000u32).map(|i| (i % 251) as u8).collect();
+        let mut compressed = Vec::new();
+        compress_stream(&mut &src[..], &mut compressed, 6).unwrap();
+        let truncated = &compressed[..compressed.len() - BLOCK_HEADER_LEN];
+
+        let err = decompress_stream(&mut &truncated[..], &mut io::sink()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let err = stream_info(&mut &truncated[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(stream_info(&mut &compressed[..]).unwrap().1, 100000);
+    }
+
+    #[test]
+    fn stream_version_1() {
+        let mut compressed = Vec::new();
+        compress_stream(&mut &b"hello"[..], &mut compressed, 6).unwrap();
+        compressed[4] = 1;
+        compressed.drain(6..8);
+        // version 1 streams end after the last block
+        compressed.truncate(compressed.len() - BLOCK_HEADER_LEN);
+
+        let mut decompressed = Vec::new();
+        decompress_stream(&mut &compressed[..], &mut decompressed).unwrap();
+        assert_eq!(decompressed, b"hello");
+        assert_eq!(stream_info(&mut &compressed[..]).unwrap().0.level, None);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn stream_parallel_keeps_block_order() {
+        let src: Vec<u8> = (0..super::STREAM_BLOCK_SIZE * 5 / 2)
+            .map(|i| (i / 4096) as u8)
+            .collect();
+        let mut compressed = Vec::new();
+        compress_stream(&mut &src[..], &mut compressed, 1).unwrap();
+
+        let mut decompressed = Vec::new();
+        decompress_stream(&mut &compressed[..], &mut decompressed).unwrap();
+        assert!(decompressed == src);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn stream_parallel_matches_sequential() {
+        let src: Vec<u8> = (0..super::STREAM_BLOCK_SIZE * 5 / 2)
+            .map(|i| (i / 4096 % 251) as u8)
+            .collect();
+        let compress_on = |threads| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            let mut compressed = Vec::new();
+            pool.install(|| compress_stream(&mut &src[..], &mut compressed, 1))
+                .unwrap();
+            compressed
+        };
+        assert!(compress_on(1) == compress_on(4));
+    }
+
+    #[test]
+    fn stream_bad_header() {
+        let mut compressed = Vec::new();
+        compress_stream(&mut &b"hello"[..], &mut compressed, 6).unwrap();
+        compressed[0] = b'X';
+        let err = decompress_stream(&mut &compressed[..], &mut io::sink()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn stream_bad_level() {
+        let err = compress_stream(&mut io::empty(), &mut io::sink(), 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
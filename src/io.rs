@@ -0,0 +1,572 @@
+/*
+ * uclcli io.rs - streaming adapters for NRV compression
+ * Copyright (C) 2020-2021  BMW Group
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! [std::io] adapters compressing data in independent blocks.
+//!
+//! The stream consists of any number of blocks, each of which is laid out as follows:
+//!
+//! | offset | size | content                                         |
+//! |--------|------|-------------------------------------------------|
+//! | 0      | 4    | uncompressed length of the block, little endian |
+//! | 4      | 4    | compressed length of the block, little endian   |
+//! | 8      | ...  | nrv2b compressed data                           |
+//!
+//! Every block can be decompressed on its own. The stream ends with an empty block header with
+//! both lengths `0`, so a stream cut off between two blocks is detected, and other data may
+//! follow the stream.
+
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+
+use crate::{
+    compress_into_buffer_with_level, decompress_into_buffer, estimate_decompressed_size,
+    minimum_compression_buffer_size, DEFAULT_LEVEL,
+};
+
+/// size of the header preceding every block
+pub const BLOCK_HEADER_LEN: usize = 8;
+/// default amount of uncompressed data per block
+pub const DEFAULT_BLOCK_SIZE: usize = 256 * 1024;
+/// largest supported amount of uncompressed data per block
+pub const MAX_BLOCK_SIZE: usize = 1024 * 1024 * 1024;
+
+/// A [Write] adapter that compresses everything written to it into a block stream.
+///
+/// Data is buffered until a full block is available, which is then compressed and written
+/// to the inner writer on the next write. Calling [flush](Write::flush) forces the current
+/// (partial) block out. The last block and the end of the stream are written on
+/// [finish](UclWriter::finish), or on drop, where errors are ignored.
+///
+/// ```
+/// use std::io::Write;
+///
+/// let mut writer = uclcli::io::UclWriter::new(Vec::new());
+/// writer.write_all(&[0; 1024]).unwrap();
+/// let compressed = writer.finish().unwrap();
+/// assert_eq!(&compressed[..8], b"\x00\x04\x00\x00\x0c\x00\x00\x00");
+/// ```
+pub struct UclWriter<W: Write> {
+    inner: Option<W>,
+    block_size: usize,
+    level: u8,
+    buf: Vec<u8>,
+    out: Vec<u8>,
+}
+
+impl<W: Write> UclWriter<W> {
+    /// create a new writer with [DEFAULT_BLOCK_SIZE]
+    pub fn new(inner: W) -> Self {
+        Self::with_block_size(inner, DEFAULT_BLOCK_SIZE)
+    }
+
+    /// create a new writer compressing `block_size` bytes at a time
+    ///
+    /// # Panics
+    /// If `block_size` is zero or larger than [MAX_BLOCK_SIZE], this function will panic.
+    pub fn with_block_size(inner: W, block_size: usize) -> Self {
+        assert!(
+            block_size > 0 && block_size <= MAX_BLOCK_SIZE,
+            "invalid block size"
+        );
+        UclWriter {
+            inner: Some(inner),
+            block_size,
+            level: DEFAULT_LEVEL,
+            buf: Vec::new(),
+            out: Vec::new(),
+        }
+    }
+
+    /// set the compression level used for subsequent blocks
+    ///
+    /// An invalid level results in an error of kind
+    /// [InvalidInput](io::ErrorKind::InvalidInput) once the next block is written.
+    pub fn set_level(&mut self, level: u8) {
+        self.level = level;
+    }
+
+    /// get a reference to the inner writer
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// write the last block and the end of the stream and return the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_block()?;
+        let mut inner = self.inner.take().unwrap();
+        write_end(&mut inner)?;
+        inner.flush()?;
+        Ok(inner)
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+
+        self.out
+            .resize(minimum_compression_buffer_size(self.buf.len()), 0);
+        let nb = compress_into_buffer_with_level(&self.buf, &mut self.out, self.level)?;
+
+        write_block(
+            self.inner.as_mut().unwrap(),
+            self.buf.len(),
+            &self.out[..nb as usize],
+        )?;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+/// write a single block of `uncompressed_len` bytes, compressed to `compressed`, to `out`
+pub(crate) fn write_block<W: Write>(
+    out: &mut W,
+    uncompressed_len: usize,
+    compressed: &[u8],
+) -> io::Result<()> {
+    out.write_all(&(uncompressed_len as u32).to_le_bytes())?;
+    out.write_all(&(compressed.len() as u32).to_le_bytes())?;
+    out.write_all(compressed)
+}
+
+/// write the empty block header ending a stream to `out`
+pub(crate) fn write_end<W: Write>(out: &mut W) -> io::Result<()> {
+    out.write_all(&[0; BLOCK_HEADER_LEN])
+}
+
+impl<W: Write> Write for UclWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        // write out a full block before taking new data, so that an error never comes with
+        // consumed data
+        if self.buf.len() == self.block_size {
+            self.write_block()?;
+        }
+        let n = data.len().min(self.block_size - self.buf.len());
+        self.buf.extend_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        self.inner.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> Drop for UclWriter<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() && self.write_block().is_ok() {
+            let _ = write_end(self.inner.as_mut().unwrap());
+        }
+    }
+}
+
+/// A [Read] adapter that decompresses a block stream as written by [UclWriter].
+///
+/// Blocks are read and decompressed one at a time, so memory usage is bounded by the block
+/// size, not the total size of the stream. Corrupt blocks result in an [io::Error] of kind
+/// [InvalidData](io::ErrorKind::InvalidData), a stream ending in the middle of a block or
+/// before its empty end block header in one of kind
+/// [UnexpectedEof](io::ErrorKind::UnexpectedEof).
+///
+/// ```
+/// use std::io::{Read, Write};
+///
+/// let mut writer = uclcli::io::UclWriter::new(Vec::new());
+/// writer.write_all(b"hello world").unwrap();
+/// let compressed = writer.finish().unwrap();
+///
+/// let mut decompressed = String::new();
+/// uclcli::io::UclReader::new(&compressed[..]).read_to_string(&mut decompressed).unwrap();
+/// assert_eq!(decompressed, "hello world");
+/// ```
+pub struct UclReader<R: Read> {
+    inner: R,
+    buf: Vec<u8>,
+    pos: usize,
+    compressed: Vec<u8>,
+    end: bool,
+    require_end: bool,
+}
+
+impl<R: Read> UclReader<R> {
+    /// create a new reader decompressing the block stream read from `inner`
+    pub fn new(inner: R) -> Self {
+        UclReader {
+            inner,
+            buf: Vec::new(),
+            pos: 0,
+            compressed: Vec::new(),
+            end: false,
+            require_end: true,
+        }
+    }
+
+    /// create a new reader for a stream which may end after its last block instead of at an
+    /// empty block header, like version 1 framed streams
+    pub(crate) fn without_end(inner: R) -> Self {
+        UclReader {
+            require_end: false,
+            ..Self::new(inner)
+        }
+    }
+
+    /// get a reference to the inner reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// return the inner reader, discarding any buffered data
+    ///
+    /// If the stream was read to its end, the inner reader is positioned right after it.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// decompress the next block into `buf`, returning `false` on a clean end of stream
+    fn read_block(&mut self) -> io::Result<bool> {
+        if self.end {
+            return Ok(false);
+        }
+        let (ulen, clen) = match read_block_header(&mut self.inner)? {
+            None if self.require_end => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Some((0, 0)) | None => {
+                self.end = true;
+                return Ok(false);
+            }
+            Some(v) => v,
+        };
+
+        // read incrementally, so a forged header alone cannot cause a large allocation
+        self.compressed.clear();
+        (&mut self.inner)
+            .take(clen as u64)
+            .read_to_end(&mut self.compressed)?;
+        if self.compressed.len() != clen {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        self.pos = 0;
+        decompress_block(&self.compressed, ulen, &mut self.buf)?;
+        Ok(true)
+    }
+}
+
+/// read the next block header from `src`, returning `None` on a clean end of stream
+pub(crate) fn read_block_header<R: Read>(src: &mut R) -> io::Result<Option<(usize, usize)>> {
+    let mut header = [0u8; BLOCK_HEADER_LEN];
+    let mut filled = 0;
+    while filled < BLOCK_HEADER_LEN {
+        match src.read(&mut header[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    parse_block_header(&header).map(Some)
+}
+
+/// parse a block header into the uncompressed and compressed length of the block
+pub(crate) fn parse_block_header(header: &[u8; BLOCK_HEADER_LEN]) -> io::Result<(usize, usize)> {
+    let ulen = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let clen = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+    if ulen > MAX_BLOCK_SIZE || clen > minimum_compression_buffer_size(ulen) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid block header",
+        ));
+    }
+    Ok((ulen, clen))
+}
+
+/// decompress the block `compressed` of `ulen` uncompressed bytes into `buf`
+///
+/// `ulen` is checked against the length encoded in the block before `buf` is grown. On error,
+/// `buf` is left empty.
+pub(crate) fn decompress_block(
+    compressed: &[u8],
+    ulen: usize,
+    buf: &mut Vec<u8>,
+) -> io::Result<()> {
+    buf.clear();
+    if estimate_decompressed_size(compressed) != Some(ulen) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "block length mismatch",
+        ));
+    }
+    buf.resize(ulen, 0);
+    let result = match decompress_into_buffer(compressed, buf) {
+        Ok(nb) if nb as usize == ulen => Ok(()),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "block length mismatch",
+        )),
+        Err(e) => Err(e.into()),
+    };
+    if result.is_err() {
+        buf.clear();
+    }
+    result
+}
+
+impl<R: Read> Read for UclReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() {
+            if out.is_empty() || !self.read_block()? {
+                return Ok(0);
+            }
+        }
+
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// A [Write] adapter counting the bytes written to the inner writer.
+pub(crate) struct CountingWriter<W: Write> {
+    pub(crate) inner: W,
+    pub(crate) count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(data)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+    use std::io::{self, Read, Write};
+
+    use super::{UclReader, UclWriter, BLOCK_HEADER_LEN};
+    use crate::decompress;
+
+    fn split_blocks(mut stream: &[u8]) -> Vec<Vec<u8>> {
+        let mut blocks = Vec::new();
+        while stream[..BLOCK_HEADER_LEN] != [0; BLOCK_HEADER_LEN] {
+            let ulen = u32::from_le_bytes(stream[..4].try_into().unwrap());
+            let clen = u32::from_le_bytes(stream[4..8].try_into().unwrap()) as usize;
+            let end = BLOCK_HEADER_LEN + clen;
+            blocks.push(decompress(&stream[BLOCK_HEADER_LEN..end], ulen).unwrap());
+            stream = &stream[end..];
+        }
+        assert_eq!(stream.len(), BLOCK_HEADER_LEN);
+        blocks
+    }
+
+    #[test]
+    fn writer_nothing() {
+        let writer = UclWriter::new(Vec::new());
+        assert_eq!(writer.finish().unwrap(), [0; BLOCK_HEADER_LEN]);
+    }
+
+    #[test]
+    fn writer_blocks() {
+        let mut writer = UclWriter::with_block_size(Vec::new(), 4);
+        writer.write_all(b"0123456789").unwrap();
+        let stream = writer.finish().unwrap();
+        assert_eq!(split_blocks(&stream), [&b"0123"[..], b"4567", b"89"]);
+    }
+
+    #[test]
+    fn writer_flush() {
+        let mut writer = UclWriter::new(Vec::new());
+        writer.write_all(b"abc").unwrap();
+        writer.flush().unwrap();
+        writer.write_all(b"def").unwrap();
+        let stream = writer.finish().unwrap();
+        assert_eq!(split_blocks(&stream), [b"abc", b"def"]);
+    }
+
+    /// a writer failing on its first write
+    struct FailOnce {
+        failed: bool,
+        out: Vec<u8>,
+    }
+
+    impl Write for FailOnce {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            if !self.failed {
+                self.failed = true;
+                return Err(io::ErrorKind::Other.into());
+            }
+            self.out.write(data)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writer_error_consumes_nothing() {
+        let inner = FailOnce {
+            failed: false,
+            out: Vec::new(),
+        };
+        let mut writer = UclWriter::with_block_size(inner, 4);
+        assert_eq!(writer.write(b"0123").unwrap(), 4);
+        assert!(writer.write(b"4567").is_err());
+        writer.write_all(b"4567").unwrap();
+        let stream = writer.finish().unwrap().out;
+        assert_eq!(split_blocks(&stream), [b"0123", b"4567"]);
+    }
+
+    #[test]
+    fn writer_drop() {
+        let mut stream = Vec::new();
+        {
+            let mut writer = UclWriter::new(&mut stream);
+            writer.write_all(b"abc").unwrap();
+        }
+        assert_eq!(split_blocks(&stream), [b"abc"]);
+    }
+
+    fn block_stream(data: &[u8], block_size: usize) -> Vec<u8> {
+        let mut writer = UclWriter::with_block_size(Vec::new(), block_size);
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn reader_nothing() {
+        let mut out = Vec::new();
+        UclReader::new(&[0; BLOCK_HEADER_LEN][..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn reader_roundtrip() {
+        let data: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+        let stream = block_stream(&data, 1000);
+        let mut out = Vec::new();
+        io::copy(&mut UclReader::new(&stream[..]), &mut out).unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn reader_partial_reads() {
+        let stream = block_stream(b"0123456789", 4);
+        let mut reader = UclReader::new(&stream[..]);
+        let mut buf = [0u8; 3];
+        let mut out = Vec::new();
+        loop {
+            let n = reader.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(out, b"0123456789");
+    }
+
+    #[test]
+    fn reader_truncated() {
+        let stream = block_stream(b"0123456789", 4);
+        for len in [3, BLOCK_HEADER_LEN + 1, stream.len() - 1].iter() {
+            let mut out = Vec::new();
+            let err = UclReader::new(&stream[..*len])
+                .read_to_end(&mut out)
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+
+    #[test]
+    fn reader_truncated_at_block_boundary() {
+        let stream = block_stream(b"0123456789", 4);
+        let without_end = &stream[..stream.len() - BLOCK_HEADER_LEN];
+        for stream in [&b""[..], without_end].iter() {
+            let mut out = Vec::new();
+            let err = UclReader::new(*stream).read_to_end(&mut out).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        }
+
+        let mut out = Vec::new();
+        UclReader::without_end(without_end)
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, b"0123456789");
+    }
+
+    #[test]
+    fn reader_corrupt() {
+        let mut stream = block_stream(b"0123456789", 4);
+        stream[0] = 5;
+        let mut out = Vec::new();
+        let err = UclReader::new(&stream[..])
+            .read_to_end(&mut out)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reader_absurd_block_length() {
+        let stream = b"\xff\xff\xff\xff\x00\x00\x00\x00";
+        let mut out = Vec::new();
+        let err = UclReader::new(&stream[..])
+            .read_to_end(&mut out)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reader_forged_block_length() {
+        let mut stream = block_stream(b"0123456789", 4);
+        stream[..4].copy_from_slice(&1_000_000u32.to_le_bytes());
+        let mut out = Vec::new();
+        let err = UclReader::new(&stream[..])
+            .read_to_end(&mut out)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut header = 1_000_000u32.to_le_bytes().to_vec();
+        header.extend_from_slice(&1_000_000u32.to_le_bytes());
+        let err = UclReader::new(&header[..])
+            .read_to_end(&mut out)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn reader_stops_at_end_marker() {
+        let mut stream = block_stream(b"0123456789", 4);
+        stream.extend_from_slice(b"trailer");
+
+        let mut reader = UclReader::new(&stream[..]);
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"0123456789");
+        assert_eq!(reader.read(&mut [0; 4]).unwrap(), 0);
+        assert_eq!(reader.into_inner(), b"trailer");
+    }
+}
@@ -16,65 +16,81 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::convert::TryInto;
-use std::mem;
-use std::ptr;
-use std::sync::atomic::{AtomicBool, Ordering};
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use core::any::Any;
+use core::convert::TryInto;
+use core::ffi::CStr;
+use core::fmt;
+use core::mem::{self, MaybeUninit};
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "std")]
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(feature = "std")]
+use std::sync::OnceLock;
 
 use libc::{c_int, c_long, c_short, c_uint, c_void};
 use thiserror::Error;
 
-const UCL_VERSION: u32 = 0x01_0300;
-
-#[link(name = "ucl")]
-extern "C" {
-    #[must_use]
-    fn __ucl_init2(
-        version: u32,
-        short: i32,
-        int: i32,
-        long: i32,
-        ucl_uint32: i32,
-        ucl_uint: i32,
-        minus_one: i32,
-        pchar: i32,
-        ucl_voidp: i32,
-        ucl_compress_t: i32,
-    ) -> c_int;
-
-    #[must_use]
-    fn ucl_nrv2b_decompress_safe_8(
-        src: *const u8,
-        src_len: c_uint,
-        dst: *mut u8,
-        dst_len: *mut c_uint,
-        wrkmem: *const c_void,
-    ) -> c_int;
-
-    #[must_use]
-    fn ucl_nrv2b_99_compress(
-        src: *const u8,
-        src_len: c_uint,
-        dst: *mut u8,
-        dst_len: *mut c_uint,
-        cb: *const c_void,
-        level: c_int,
-        conf: *const c_void,
-        result: *const c_void,
-    ) -> c_int;
-}
+#[cfg(feature = "bytes")]
+pub mod bytes;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod codec;
+pub mod frame;
+#[cfg(feature = "std")]
+pub mod io;
+#[cfg(feature = "tokio")]
+pub mod tokio;
+
+pub use frame::{compress_framed, compress_framed_with_level, decompress_framed};
 
+mod sys;
+
+use sys::{
+    __ucl_init2, ucl_adler32, ucl_compress_config_t, ucl_crc32, ucl_nrv2b_99_compress,
+    ucl_nrv2b_decompress_safe_8, ucl_nrv2b_decompress_safe_le16, ucl_nrv2b_decompress_safe_le32,
+    ucl_nrv2b_test_overlap_8, ucl_progress_callback_t, ucl_uint, ucl_version, ucl_version_string,
+    ucl_voidp, UCL_VERSION,
+};
+
+#[cfg(feature = "std")]
+static INIT_RESULT: OnceLock<core::result::Result<(), UclInitError>> = OnceLock::new();
 static INITIALIZED: AtomicBool = AtomicBool::new(false);
 
-/// initializes libucl
-///
-/// call this once before calling any other function in this package
-///
-/// # Panics
-/// If initialization failed for some reason, this function will panic.
-pub fn ucl_init() {
-    unsafe {
-        let res = __ucl_init2(
+/// Reasons why libucl could not be initialized, see [ucl_init_checked].
+#[derive(Error, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum UclInitError {
+    /// the linked libucl has a different major version than these bindings, or is older
+    #[error("incompatible libucl version {found:#08x}, expected {expected:#08x}")]
+    VersionMismatch { found: u32, expected: u32 },
+    /// libucl was built with different type sizes than these bindings, or failed its self-test
+    #[error("libucl initialization failed with error {0}, incompatible architecture?")]
+    SizeMismatch(i32),
+}
+
+/// whether a libucl reporting version `found` can be used with these bindings
+fn is_compatible_version(found: u32) -> bool {
+    found >> 16 == UCL_VERSION >> 16 && found >= UCL_VERSION
+}
+
+fn init_once() -> core::result::Result<(), UclInitError> {
+    let found = library_version();
+    if !is_compatible_version(found) {
+        return Err(UclInitError::VersionMismatch {
+            found,
+            expected: UCL_VERSION,
+        });
+    }
+
+    let res = unsafe {
+        __ucl_init2(
             UCL_VERSION,
             mem::size_of::<c_short>() as i32,
             mem::size_of::<c_int>() as i32,
@@ -85,16 +101,99 @@ pub fn ucl_init() {
             mem::size_of::<*mut u8>() as i32,
             mem::size_of::<*mut c_void>() as i32,
             mem::size_of::<*mut c_void>() as i32, // function ptr
-        );
-        assert!(
-            res == 0,
-            "ucl init failed. incompatible library version or architecture?"
-        );
+        )
+    };
+    match res {
+        0 => Ok(()),
+        _ => Err(UclInitError::SizeMismatch(res)),
+    }
+}
+
+/// initializes libucl, reporting failure as an error
+///
+/// This behaves like [ucl_init], but leaves the failure policy to the caller, e.g. to fall back
+/// to a different codec if the linked libucl is incompatible. Every call returns the same result.
+///
+/// ```
+/// assert_eq!(uclcli::ucl_init_checked(), Ok(()));
+/// ```
+pub fn ucl_init_checked() -> core::result::Result<(), UclInitError> {
+    #[cfg(feature = "std")]
+    let result = *INIT_RESULT.get_or_init(init_once);
+    // without std there is no OnceLock, but initializing libucl more than once is harmless
+    #[cfg(not(feature = "std"))]
+    let result = if INITIALIZED.load(Ordering::Acquire) {
+        Ok(())
+    } else {
+        init_once()
+    };
+    if result.is_ok() {
+        INITIALIZED.store(true, Ordering::Release);
     }
-    INITIALIZED.store(true, Ordering::Release);
+    result
 }
 
+/// initializes libucl
+///
+/// All functions in this package initialize libucl on first use, so calling this is optional.
+/// Call it to front-load initialization (and a possible failure) to a well-defined point. It is
+/// safe to call this any number of times from any number of threads, libucl will only be
+/// initialized once.
+///
+/// # Panics
+/// If initialization failed for some reason, this function will panic (on every call). See
+/// [ucl_init_checked] for a non-panicking variant.
+pub fn ucl_init() {
+    ucl_init_checked().expect("ucl init failed. incompatible library version or architecture?");
+}
+
+/// version of the linked libucl, e.g. `0x010300` for 1.03
+///
+/// This does not require libucl to be initialized.
+///
+/// ```
+/// assert!(uclcli::library_version() >= 0x010300);
+/// ```
+pub fn library_version() -> u32 {
+    unsafe { ucl_version() }
+}
+
+/// version of the linked libucl as a human readable string, e.g. `"1.03"`
+///
+/// This does not require libucl to be initialized.
+///
+/// ```
+/// println!("linked against libucl {}", uclcli::library_version_string());
+/// ```
+pub fn library_version_string() -> String {
+    unsafe { CStr::from_ptr(ucl_version_string()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// forget that libucl was initialized, so the next call initializes it again
+///
+/// The result of the first initialization is kept with `std`, so this only makes the lazy
+/// initialization paths testable. Tests running concurrently are not affected beyond taking
+/// those paths themselves.
+#[cfg(test)]
+fn reset_for_test() {
+    INITIALIZED.store(false, Ordering::Release);
+}
+
+/// initializes libucl on first use, see [ucl_init]
+fn ensure_init() {
+    if !INITIALIZED.load(Ordering::Acquire) {
+        ucl_init();
+    }
+}
+
+/// Errors returned by the compression and decompression functions.
+///
+/// New variants may be added in minor releases, so matches on this type need a wildcard arm.
 #[derive(Error, Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum UclErrorKind {
     #[error("generic UCL error")]
     GenericError,
@@ -120,13 +219,81 @@ pub enum UclErrorKind {
     SrcTooLarge,
     #[error("dst buffer too large")]
     DstTooLarge,
-    #[error("dst buffer too small")]
-    DstTooSmall,
+    /// `dst` must be at least `needed` bytes, but only `got` bytes were given
+    #[error("dst buffer too small, need {needed} bytes but got {got}")]
+    DstTooSmall { needed: usize, got: usize },
+    /// the decompressed data does not match the checksum recorded when compressing it
+    #[error("checksum mismatch")]
+    ChecksumMismatch,
+    #[error("unknown UCL error {0}")]
+    Unknown(i32),
+    #[cfg(feature = "std")]
+    #[error("I/O error: {0}")]
+    Io(#[cfg_attr(feature = "serde", serde(with = "io_error_kind"))] std::io::ErrorKind),
+}
+
+/// Error returned by [try_decompress_all] if one of the blocks cannot be decompressed.
+#[derive(Error, Debug, PartialEq, Eq, Clone, Copy)]
+#[error("block {decoded} at offset {offset}: {kind}")]
+pub struct DecompressAllError {
+    /// number of blocks successfully decompressed before the failing one
+    pub decoded: usize,
+    /// offset of the failing block in `src`
+    pub offset: usize,
+    /// why the block could not be decompressed
+    #[source]
+    pub kind: UclErrorKind,
+}
+
+/// serializes [std::io::ErrorKind], which has no serde support, by its variant name
+#[cfg(feature = "serde")]
+mod io_error_kind {
+    use std::io::ErrorKind;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    const KINDS: &[ErrorKind] = &[
+        ErrorKind::NotFound,
+        ErrorKind::PermissionDenied,
+        ErrorKind::ConnectionRefused,
+        ErrorKind::ConnectionReset,
+        ErrorKind::ConnectionAborted,
+        ErrorKind::NotConnected,
+        ErrorKind::AddrInUse,
+        ErrorKind::AddrNotAvailable,
+        ErrorKind::BrokenPipe,
+        ErrorKind::AlreadyExists,
+        ErrorKind::WouldBlock,
+        ErrorKind::InvalidInput,
+        ErrorKind::InvalidData,
+        ErrorKind::TimedOut,
+        ErrorKind::WriteZero,
+        ErrorKind::Interrupted,
+        ErrorKind::Unsupported,
+        ErrorKind::UnexpectedEof,
+        ErrorKind::OutOfMemory,
+        ErrorKind::Other,
+    ];
+
+    pub fn serialize<S: Serializer>(kind: &ErrorKind, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{:?}", kind))
+    }
+
+    /// unknown names, e.g. from a newer std, deserialize as [ErrorKind::Other]
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ErrorKind, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(KINDS
+            .iter()
+            .copied()
+            .find(|kind| format!("{:?}", kind) == name)
+            .unwrap_or(ErrorKind::Other))
+    }
 }
 
 impl UclErrorKind {
     fn from(code: i32) -> Self {
         match code {
+            -1 => UclErrorKind::GenericError,
             -2 => UclErrorKind::InvalidArgument,
             -3 => UclErrorKind::OutOfMemory,
             -101 => UclErrorKind::NotCompressible,
@@ -136,37 +303,106 @@ impl UclErrorKind {
             -204 => UclErrorKind::EofNotFound,
             -205 => UclErrorKind::InputNotConsumed,
             -206 => UclErrorKind::OverlapOverrun,
-            _ => UclErrorKind::GenericError,
+            _ => UclErrorKind::Unknown(code),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<UclErrorKind> for std::io::Error {
+    fn from(kind: UclErrorKind) -> Self {
+        use std::io::ErrorKind;
+
+        let io_kind = match kind {
+            UclErrorKind::InputOverrun
+            | UclErrorKind::OutputOverrun
+            | UclErrorKind::LookbehindOverrun
+            | UclErrorKind::EofNotFound
+            | UclErrorKind::InputNotConsumed
+            | UclErrorKind::OverlapOverrun
+            | UclErrorKind::ChecksumMismatch => ErrorKind::InvalidData,
+            UclErrorKind::InvalidArgument
+            | UclErrorKind::SrcTooLarge
+            | UclErrorKind::DstTooLarge
+            | UclErrorKind::DstTooSmall { .. } => ErrorKind::InvalidInput,
+            UclErrorKind::GenericError
+            | UclErrorKind::OutOfMemory
+            | UclErrorKind::NotCompressible
+            | UclErrorKind::Unknown(_) => ErrorKind::Other,
+            UclErrorKind::Io(io_kind) => io_kind,
+        };
+        std::io::Error::new(io_kind, kind)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for UclErrorKind {
+    fn from(err: std::io::Error) -> Self {
+        match err.get_ref().and_then(|e| e.downcast_ref::<UclErrorKind>()) {
+            Some(kind) => *kind,
+            None => UclErrorKind::Io(err.kind()),
         }
     }
 }
 
+/// SAFETY: dst must be valid for writes of dst_capacity bytes
+///
+/// Nothing in here may panic while libucl is running, unwinding across the FFI boundary is UB.
 unsafe fn decompress_ptr(
     src: &[u8],
     dst: *mut u8,
     dst_capacity: u32,
-) -> std::result::Result<u32, UclErrorKind> {
-    assert!(
-        INITIALIZED.load(Ordering::Acquire),
-        "ucl_init was not called before attempting decompression"
-    );
+) -> core::result::Result<u32, UclErrorKind> {
+    ensure_init();
+    decompress_ptr_initialized(src, dst, dst_capacity)
+}
+
+/// like [decompress_ptr], but libucl must already be initialized
+#[inline]
+unsafe fn decompress_ptr_initialized(
+    src: &[u8],
+    dst: *mut u8,
+    dst_capacity: u32,
+) -> core::result::Result<u32, UclErrorKind> {
+    decompress_ptr_using(ucl_nrv2b_decompress_safe_8, src, dst, dst_capacity)
+}
+
+/// signature of libucl's safe decompressors, e.g. `ucl_nrv2b_decompress_safe_8`
+pub(crate) type DecompressFn =
+    unsafe extern "C" fn(*const u8, ucl_uint, *mut u8, *mut ucl_uint, ucl_voidp) -> c_int;
 
+/// like [decompress_ptr_initialized], but using `decompress_fn` instead of nrv2b
+#[inline]
+unsafe fn decompress_ptr_using(
+    decompress_fn: DecompressFn,
+    src: &[u8],
+    dst: *mut u8,
+    dst_capacity: u32,
+) -> core::result::Result<u32, UclErrorKind> {
+    // even a stream of nothing takes 8 bytes
+    if src.is_empty() {
+        return Err(UclErrorKind::InvalidArgument);
+    }
     let src_len = match src.len().try_into() {
         Ok(v) => v,
         Err(_) => return Err(UclErrorKind::SrcTooLarge),
     };
 
+    // an empty `dst` may be dangling, e.g. from `Vec::with_capacity(0)`, so never hand it to
+    // libucl. It must not write to the scratch byte either, as it is given a capacity of 0.
+    let mut scratch = 0u8;
+    let dst = if dst_capacity == 0 {
+        &mut scratch as *mut u8
+    } else {
+        dst
+    };
     let mut dst_len = dst_capacity;
 
-    let res = ucl_nrv2b_decompress_safe_8(src.as_ptr(), src_len, dst, &mut dst_len, ptr::null());
+    let res = decompress_fn(src.as_ptr(), src_len, dst, &mut dst_len, ptr::null_mut());
+    // report a bogus length as an error instead of panicking
     match res {
-        0 => {
-            assert!(
-                dst_len <= (dst_capacity as u32),
-                "decompressen yielded more data than available in dst buffer"
-            );
-            Ok(dst_len)
-        }
+        0 if dst_len <= dst_capacity => Ok(dst_len),
+        0 => Err(UclErrorKind::OutputOverrun),
         _ => Err(UclErrorKind::from(res)),
     }
 }
@@ -174,14 +410,16 @@ unsafe fn decompress_ptr(
 /// decompress a NRV compressed buffer into another buffer
 ///
 /// If `dst` is not big enough to hold the
-/// decompressed buffer, this will return `Err(UclErrorKind::OutputOverrun)`.
+/// decompressed buffer, this will return `Err(UclErrorKind::OutputOverrun)`. If `src`
+/// continues after the end of the compressed data, this will return
+/// `Err(UclErrorKind::InputNotConsumed)`. If `src` is empty, this will return
+/// `Err(UclErrorKind::InvalidArgument)`, as no valid stream is empty.
 /// If decompression succeeded, this will return the number of usable bytes in `dst`.
 ///
 /// # Panics
-/// If [ucl_init] was not called prior to calling this function, this function will panic.
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
 ///
 /// ```
-/// # uclcli::ucl_init();
 /// let mut buf = [0xffu8; 1024];
 /// assert_eq!(uclcli::decompress_into_buffer(b"\x92\xa5\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff", &mut buf), Ok(1024));
 /// assert_eq!(buf, [0xa5u8; 1024]);
@@ -189,7 +427,7 @@ unsafe fn decompress_ptr(
 pub fn decompress_into_buffer(
     src: &[u8],
     dst: &mut [u8],
-) -> std::result::Result<u32, UclErrorKind> {
+) -> core::result::Result<u32, UclErrorKind> {
     let dst_len = match dst.len().try_into() {
         Ok(v) => v,
         Err(_) => return Err(UclErrorKind::DstTooLarge),
@@ -198,247 +436,2349 @@ pub fn decompress_into_buffer(
     unsafe { decompress_ptr(src, dst.as_mut_ptr(), dst_len) }
 }
 
-/// decompress a NRV compressed buffer into a newly allocated buffer
+/// Like [decompress_into_buffer], but allowing `src` to continue after the end of the
+/// compressed data.
 ///
-/// If `dst_capacity` is not enough to hold the decompressed buffer, this will
-/// return `Err(UclErrorKind::OutputOverrun)`.
-/// If decompression succeeded, this will return the decompressed buffer.
+/// libucl does not report how much of `src` it read, so the end of the compressed data is found
+/// by walking the bitstream first, like [estimate_decompressed_size] does. If decompression
+/// succeeded, this will return the number of usable bytes in `dst` and the number of bytes of
+/// `src` that were consumed, so that several concatenated nrv2b streams can be decompressed one
+/// after the other.
 ///
 /// # Panics
-/// If [ucl_init] was not called prior to calling this function, this function will panic.
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
 ///
 /// ```
-/// # uclcli::ucl_init();
-/// assert_eq!(uclcli::decompress(b"\x92\xa5\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff", 1024).unwrap(), [0xa5u8; 1024]);
+/// let block = b"\x92\xa5\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff";
+/// let src = [&block[..], &block[..]].concat();
+/// let mut buf = [0u8; 1024];
+/// assert_eq!(uclcli::decompress_into_buffer_consumed(&src, &mut buf), Ok((1024, 12)));
+/// assert_eq!(uclcli::decompress_into_buffer_consumed(&src[12..], &mut buf), Ok((1024, 12)));
 /// ```
-pub fn decompress(src: &[u8], dst_capacity: u32) -> std::result::Result<Vec<u8>, UclErrorKind> {
-    let mut dst = Vec::with_capacity(dst_capacity as usize);
-
-    unsafe {
-        let new_length = decompress_ptr(src, dst.as_mut_ptr(), dst_capacity)?;
-        dst.set_len(new_length as usize);
-    }
-
-    Ok(dst)
+pub fn decompress_into_buffer_consumed(
+    src: &[u8],
+    dst: &mut [u8],
+) -> core::result::Result<(u32, u32), UclErrorKind> {
+    // let libucl diagnose streams that cannot be walked
+    let consumed = scan_stream(src, BitBuffer::Bits8).map_or(src.len(), |(_, ilen)| ilen);
+    let nb = decompress_into_buffer(&src[..consumed], dst)?;
+    Ok((nb, consumed as u32))
 }
 
-/// Determine the destination buffer size requirement for [compress_into_buffer].
+/// decompress a concatenation of independently compressed NRV buffers
 ///
-/// citing from libucl's README:
+/// The blocks are decompressed one after the other, each into a buffer of exactly its
+/// decompressed size, until `src` is exhausted. If a block would decompress to more than
+/// `per_block_cap` bytes, or cannot be decompressed at all, this will return an error holding
+/// the number of blocks decompressed before it, its offset in `src` and its
+/// [UclErrorKind].
 ///
-/// > UCL will expand non-compressible data by a little amount. I suggest
-/// > using this formula for a worst-case expansion calculation:
-/// >
-/// >   output_block_size = input_block_size + (input_block_size / 8) + 256
-pub const fn minimum_compression_buffer_size(src_len: usize) -> usize {
-    src_len + (src_len / 8) + 256
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+///
+/// ```
+/// let block = b"\x92\xa5\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff";
+/// let src = [&block[..], &block[..]].concat();
+/// let blocks = uclcli::try_decompress_all(&src, 1024).unwrap();
+/// assert_eq!(blocks, [[0xa5u8; 1024], [0xa5u8; 1024]]);
+///
+/// let err = uclcli::try_decompress_all(&src[..20], 1024).unwrap_err();
+/// assert_eq!((err.decoded, err.offset), (1, 12));
+/// ```
+pub fn try_decompress_all(
+    src: &[u8],
+    per_block_cap: u32,
+) -> core::result::Result<Vec<Vec<u8>>, DecompressAllError> {
+    let mut blocks = Vec::new();
+    let mut offset = 0;
+    while offset < src.len() {
+        let (block, consumed) =
+            decompress_block(&src[offset..], per_block_cap).map_err(|kind| DecompressAllError {
+                decoded: blocks.len(),
+                offset,
+                kind,
+            })?;
+        blocks.push(block);
+        offset += consumed;
+    }
+    Ok(blocks)
 }
 
-/// SAFETY: dst_capacity must be >= minimum_compressed_buffer_size(src.len())
-unsafe fn compress_ptr(
+/// decompress the block at the start of `src`, returning it and the number of bytes it took up
+fn decompress_block(
     src: &[u8],
-    dst: *mut u8,
     dst_capacity: u32,
-) -> std::result::Result<u32, UclErrorKind> {
-    assert!(
-        INITIALIZED.load(Ordering::Acquire),
-        "ucl_init was not called before attempting decompression"
-    );
-
-    let src_len = match src.len().try_into() {
-        Ok(v) => v,
-        Err(_) => return Err(UclErrorKind::SrcTooLarge),
-    };
-
-    let mut dst_len = dst_capacity;
-
-    let res = ucl_nrv2b_99_compress(
-        src.as_ptr(),
-        src_len,
-        dst,
-        &mut dst_len,
-        ptr::null(), /* no progress callback */
-        6,
-        ptr::null(), /* default compression config */
-        ptr::null(), /* no statistical output */
-    );
-    match res {
-        0 => {
-            assert!(
-                dst_len <= (dst_capacity as u32),
-                "decompressen yielded more data than available in dst buffer"
-            );
-            Ok(dst_len)
+) -> core::result::Result<(Vec<u8>, usize), UclErrorKind> {
+    match scan_stream(src, BitBuffer::Bits8) {
+        Some((olen, ilen)) if olen <= dst_capacity as usize => {
+            decompress(&src[..ilen], olen as u32).map(|block| (block, ilen))
         }
-        _ => Err(UclErrorKind::from(res)),
+        Some(_) => Err(UclErrorKind::OutputOverrun),
+        // let libucl diagnose streams that cannot be walked
+        None => decompress(src, dst_capacity).map(|block| (block, src.len())),
     }
 }
 
-/// NRV compress a buffer into another buffer.
+/// Like [decompress_into_buffer], but with less overhead per call for hot loops over small
+/// buffers.
 ///
-/// If `dst` is not big enough to hold the compressed
-/// buffer, this will return `Err(UclErrorKind::DstTooSmall)`. See also
-/// [minimum_compression_buffer_size] to find out how big `dst` should be.
-/// If compression succeeded, this will return the number of usable bytes in `dst`.
+/// The caller must make sure that `dst` is at most `u32::MAX` bytes long. Unlike
+/// [decompress_into_buffer], this is not checked: only the first `u32::MAX` bytes of a larger
+/// `dst` are used. Initialization of libucl is only checked with a relaxed load, so
+/// [ucl_init] should be called once before the first call from a new thread. Both preconditions
+/// only affect the result, this function is memory-safe either way.
 ///
 /// # Panics
-/// If [ucl_init] was not called prior to calling this function, this function will panic.
-/// ```
-/// # uclcli::ucl_init();
-/// let src = [0; 1024];
-/// let mut dst = vec![0xffu8; uclcli::minimum_compression_buffer_size(src.len())];
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
 ///
-/// let result = uclcli::compress_into_buffer(&src, &mut dst);
-/// assert_eq!(result, Ok(12));
+/// ```
+/// uclcli::ucl_init();
+/// let mut buf = [0xffu8; 1024];
+/// assert_eq!(uclcli::decompress_into_buffer_unchecked(b"\x92\xa5\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff", &mut buf), Ok(1024));
+/// ```
+#[inline]
+pub fn decompress_into_buffer_unchecked(
+    src: &[u8],
+    dst: &mut [u8],
+) -> core::result::Result<u32, UclErrorKind> {
+    if !INITIALIZED.load(Ordering::Relaxed) {
+        ucl_init();
+    }
+    let dst_len = dst.len().min(u32::MAX as usize) as u32;
+
+    unsafe { decompress_ptr_initialized(src, dst.as_mut_ptr(), dst_len) }
+}
+
+/// Like [decompress_into_buffer], but returning the number of usable bytes as `usize`.
 ///
-/// let nb = result.unwrap() as usize;
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
 ///
-/// assert_eq!(&dst[..nb], b"\x92\x00\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff");
-/// assert_eq!(&dst[nb..], &vec![0xffu8; dst.len() - nb]);
 /// ```
-pub fn compress_into_buffer(src: &[u8], dst: &mut [u8]) -> std::result::Result<u32, UclErrorKind> {
-    if dst.len() < minimum_compression_buffer_size(src.len()) {
-        return Err(UclErrorKind::DstTooSmall);
-    }
+/// let mut buf = [0xffu8; 1024];
+/// assert_eq!(uclcli::decompress_into_slice(b"\x92\xa5\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff", &mut buf), Ok(1024usize));
+/// ```
+pub fn decompress_into_slice(
+    src: &[u8],
+    dst: &mut [u8],
+) -> core::result::Result<usize, UclErrorKind> {
+    decompress_into_buffer(src, dst).map(|nb| nb as usize)
+}
 
+/// Like [decompress_into_buffer], but decompressing into uninitialized memory.
+///
+/// This avoids zeroing `dst` first, e.g. for buffers from an arena allocator. libucl only
+/// writes to the beginning of `dst`, so if decompression succeeded, the first `n` bytes of
+/// `dst` are initialized, where `n` is the returned length. The rest of `dst` is left as it
+/// was, so it may still be uninitialized. On error, any part of `dst` may have been written,
+/// but none of it is guaranteed to be initialized.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+///
+/// ```
+/// use std::mem::MaybeUninit;
+///
+/// let mut buf = [MaybeUninit::<u8>::uninit(); 2048];
+/// let nb = uclcli::decompress_into_uninit(b"\x92\xa5\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff", &mut buf).unwrap();
+/// assert_eq!(nb, 1024);
+/// // SAFETY: the first `nb` bytes were initialized by decompression
+/// let data = unsafe { std::slice::from_raw_parts(buf.as_ptr() as *const u8, nb as usize) };
+/// assert_eq!(data, [0xa5u8; 1024]);
+/// ```
+pub fn decompress_into_uninit(
+    src: &[u8],
+    dst: &mut [MaybeUninit<u8>],
+) -> core::result::Result<u32, UclErrorKind> {
     let dst_len = match dst.len().try_into() {
         Ok(v) => v,
         Err(_) => return Err(UclErrorKind::DstTooLarge),
     };
 
-    unsafe { compress_ptr(src, dst.as_mut_ptr(), dst_len) }
+    unsafe { decompress_ptr(src, dst.as_mut_ptr().cast(), dst_len) }
 }
 
-/// NRV compress a buffer into a newly allocated buffer.
+/// Like [decompress_into_buffer], but taking raw pointers, e.g. to compressed data linked at a
+/// fixed flash address.
+///
+/// Unlike all other functions, this does not make sure that libucl is initialized, to keep
+/// the boot path of embedded targets minimal. If `src_len` is `0`, this will return
+/// `Err(UclErrorKind::InvalidArgument)` without reading `src`.
+///
+/// # Safety
+/// [ucl_init] or [ucl_init_checked] must have been called successfully before. `src` must be
+/// valid for reads of `src_len` bytes and `dst` must be valid for writes of `dst_cap` bytes,
+/// and the two regions must not overlap. Either may be null if its length is `0`.
 ///
-/// # Panics
-/// If [ucl_init] was not called prior to calling this function, this function will panic.
 /// ```
-/// # uclcli::ucl_init();
-/// let src = [0; 1024];
+/// uclcli::ucl_init();
+/// let src = b"\x92\xa5\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff";
+/// let mut buf = [0u8; 1024];
+/// let nb = unsafe { uclcli::decompress_raw(src.as_ptr(), src.len() as u32, buf.as_mut_ptr(), 1024) };
+/// assert_eq!(nb, Ok(1024));
+/// assert_eq!(buf, [0xa5u8; 1024]);
+/// ```
+pub unsafe fn decompress_raw(
+    src: *const u8,
+    src_len: u32,
+    dst: *mut u8,
+    dst_cap: u32,
+) -> core::result::Result<u32, UclErrorKind> {
+    if src_len == 0 {
+        return Err(UclErrorKind::InvalidArgument);
+    }
+    let src = core::slice::from_raw_parts(src, src_len as usize);
+    decompress_ptr_initialized(src, dst, dst_cap)
+}
+
+/// decompress a NRV compressed buffer into a newly allocated buffer
+///
+/// If `dst_capacity` is not enough to hold the decompressed buffer, this will
+/// return `Err(UclErrorKind::OutputOverrun)`. If `src` continues after the end of the
+/// compressed data, e.g. because two files were concatenated, this will return
+/// `Err(UclErrorKind::InputNotConsumed)`. If `src` is empty, this will return
+/// `Err(UclErrorKind::InvalidArgument)`. A `dst_capacity` of `0` is only enough for a stream of
+/// nothing.
+/// If decompression succeeded, this will return the decompressed buffer.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
 ///
-/// assert_eq!(uclcli::compress(&src).unwrap(), b"\x92\x00\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff");
 /// ```
-pub fn compress(src: &[u8]) -> std::result::Result<Vec<u8>, UclErrorKind> {
-    let capacity = minimum_compression_buffer_size(src.len());
-    let mut dst = Vec::with_capacity(capacity);
+/// assert_eq!(uclcli::decompress(b"\x92\xa5\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff", 1024).unwrap(), [0xa5u8; 1024]);
+/// ```
+pub fn decompress(src: &[u8], dst_capacity: u32) -> core::result::Result<Vec<u8>, UclErrorKind> {
+    decompress_using(ucl_nrv2b_decompress_safe_8, src, dst_capacity)
+}
 
-    let dst_len = match capacity.try_into() {
-        Ok(v) => v,
-        Err(_) => return Err(UclErrorKind::DstTooLarge),
-    };
+/// like [decompress], but using `decompress_fn` instead of nrv2b
+pub(crate) fn decompress_using(
+    decompress_fn: DecompressFn,
+    src: &[u8],
+    dst_capacity: u32,
+) -> core::result::Result<Vec<u8>, UclErrorKind> {
+    ensure_init();
+    let mut dst = Vec::with_capacity(dst_capacity as usize);
+    // never hand libucl more than was actually allocated, whatever the allocator did
+    let dst_capacity = dst.capacity().min(dst_capacity as usize) as u32;
 
     unsafe {
-        let new_length = compress_ptr(src, dst.as_mut_ptr(), dst_len)?;
+        let new_length = decompress_ptr_using(decompress_fn, src, dst.as_mut_ptr(), dst_capacity)?;
         dst.set_len(new_length as usize);
     }
+
     Ok(dst)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::{compress_into_buffer, decompress, decompress_into_buffer, ucl_init, UclErrorKind};
+/// Like [decompress], but taking the capacity as `usize`.
+///
+/// If `dst_capacity` exceeds `u32::MAX`, this will return `Err(UclErrorKind::DstTooLarge)`.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+///
+/// ```
+/// let src = b"\x92\xa5\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff";
+/// assert_eq!(uclcli::decompress_with_capacity(src, 1024).unwrap(), [0xa5u8; 1024]);
+/// ```
+pub fn decompress_with_capacity(
+    src: &[u8],
+    dst_capacity: usize,
+) -> core::result::Result<Vec<u8>, UclErrorKind> {
+    let dst_capacity = match dst_capacity.try_into() {
+        Ok(v) => v,
+        Err(_) => return Err(UclErrorKind::DstTooLarge),
+    };
+    decompress(src, dst_capacity)
+}
 
-    #[test]
-    fn compress_buffer_nothing() {
-        ucl_init();
-        let mut buf = [0xa5u8; 256];
-        assert_eq!(compress_into_buffer(&[], &mut buf).unwrap(), 8);
-        assert_eq!(&buf[..8], b"\x00\x00\x00\x00\x00\x04\x80\xff");
-        assert!(&buf[8..].iter().all(|b| *b == 0xa5));
+/// Word size and byte order in which an nrv2b stream stores its bits.
+///
+/// libucl's compressor can pack the bits controlling decompression into bytes, the default, or
+/// into 16 or 32 bit little endian words, which some decompressors on other platforms expect.
+/// The packing changes where literal bytes end up in the stream, so a stream can only be
+/// decompressed with the packing it was compressed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BitBuffer {
+    /// bits packed into bytes, as produced by [compress] and expected by [decompress]
+    #[default]
+    Bits8,
+    /// bits packed into 16 bit little endian words
+    Le16,
+    /// bits packed into 32 bit little endian words
+    Le32,
+}
+
+impl BitBuffer {
+    /// libucl's safe nrv2b decompressor for this packing
+    fn decompress_fn(self) -> DecompressFn {
+        match self {
+            BitBuffer::Bits8 => ucl_nrv2b_decompress_safe_8,
+            BitBuffer::Le16 => ucl_nrv2b_decompress_safe_le16,
+            BitBuffer::Le32 => ucl_nrv2b_decompress_safe_le32,
+        }
     }
+}
 
-    #[test]
-    fn compress_buffer_too_small() {
-        ucl_init();
-        let mut buf = [0xa5u8; 4];
-        assert_eq!(
-            compress_into_buffer(b"\xde\xad\xbe\xef", &mut buf).unwrap_err(),
-            UclErrorKind::DstTooSmall
-        );
-        assert!(buf.iter().all(|b| *b == 0xa5));
+/// Like [decompress], but for a stream whose bits are packed as given by `bit_buffer`.
+///
+/// With [BitBuffer::Bits8], this is the same as [decompress]. libucl's decompressors for the
+/// wider packings may read up to a word past the end of a malformed stream, so for those, the
+/// stream is checked like [estimate_decompressed_size] does before it is decompressed. If it is
+/// not a complete nrv2b stream with this packing, e.g. because it was compressed with another
+/// one, this will return `Err(UclErrorKind::InvalidArgument)`, or
+/// `Err(UclErrorKind::InputNotConsumed)` if `src` continues after its end.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+///
+/// ```
+/// use uclcli::{BitBuffer, UclErrorKind};
+///
+/// let le16 = b"\xaa\x92\xa5\x00\xa1\x00\x00\x00\x00\x80\x04\xff";
+/// assert_eq!(uclcli::decompress_with_bit_buffer(le16, 1024, BitBuffer::Le16).unwrap(), [0xa5u8; 1024]);
+/// assert_eq!(uclcli::decompress_with_bit_buffer(le16, 1024, BitBuffer::Le32), Err(UclErrorKind::InvalidArgument));
+/// ```
+pub fn decompress_with_bit_buffer(
+    src: &[u8],
+    dst_capacity: u32,
+    bit_buffer: BitBuffer,
+) -> core::result::Result<Vec<u8>, UclErrorKind> {
+    if bit_buffer != BitBuffer::Bits8 {
+        match scan_stream(src, bit_buffer) {
+            Some((_, ilen)) if ilen == src.len() => {}
+            Some(_) => return Err(UclErrorKind::InputNotConsumed),
+            None => return Err(UclErrorKind::InvalidArgument),
+        }
+    }
+    decompress_using(bit_buffer.decompress_fn(), src, dst_capacity)
+}
+
+/// Determine the decompressed size of a NRV compressed buffer without decompressing it.
+///
+/// nrv2b streams carry no length field, but the length of every literal run and match is
+/// encoded in the bitstream. This walks the bitstream like libucl's safe decompressor does,
+/// without writing any output, so it takes time linear in `src.len()` but no memory.
+///
+/// Returns `None` if `src` is not a complete nrv2b stream, i.e. if it is truncated, refers to
+/// data before the start of the output, or continues after the end of the compressed data.
+/// Otherwise, decompressing `src` needs a buffer of exactly the returned size.
+///
+/// ```
+/// let src = b"\x92\xa5\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff";
+/// assert_eq!(uclcli::estimate_decompressed_size(src), Some(1024));
+/// assert_eq!(uclcli::estimate_decompressed_size(&src[..8]), None);
+/// ```
+pub fn estimate_decompressed_size(src: &[u8]) -> Option<usize> {
+    match scan_stream(src, BitBuffer::Bits8) {
+        Some((olen, ilen)) if ilen == src.len() => Some(olen),
+        _ => None,
+    }
+}
+
+/// walk the nrv2b bitstream at the start of `src` up to its end marker, see
+/// [estimate_decompressed_size], reading its bits in words of `bit_buffer`
+///
+/// Returns the decompressed size and the number of bytes of `src` taken up by the stream, or
+/// `None` if the stream is truncated or refers to data before the start of the output.
+fn scan_stream(src: &[u8], bit_buffer: BitBuffer) -> Option<(usize, usize)> {
+    let mut ilen = 0;
+    let mut olen = 0usize;
+    let mut bb = 0u32;
+    let mut bc = 0u32;
+    let mut last_m_off = 1u32;
+
+    let mut getbit = |ilen: &mut usize| -> Option<u32> {
+        let word_len = match bit_buffer {
+            BitBuffer::Bits8 => {
+                if bb & 0x7f != 0 {
+                    bb *= 2;
+                } else {
+                    bb = u32::from(*src.get(*ilen)?) * 2 + 1;
+                    *ilen += 1;
+                }
+                return Some((bb >> 8) & 1);
+            }
+            BitBuffer::Le16 => 2,
+            BitBuffer::Le32 => 4,
+        };
+        if bc == 0 {
+            let word = src.get(*ilen..*ilen + word_len)?;
+            bb = word.iter().rev().fold(0, |bb, b| bb << 8 | u32::from(*b));
+            bc = word_len as u32 * 8;
+            *ilen += word_len;
+        }
+        bc -= 1;
+        Some((bb >> bc) & 1)
+    };
+
+    loop {
+        while getbit(&mut ilen)? == 1 {
+            src.get(ilen)?;
+            ilen += 1;
+            olen = olen.checked_add(1)?;
+        }
+
+        let mut m_off = 1u32;
+        loop {
+            m_off = m_off * 2 + getbit(&mut ilen)?;
+            if m_off > 0xff_ffff + 3 {
+                return None;
+            }
+            if getbit(&mut ilen)? == 1 {
+                break;
+            }
+        }
+        if m_off == 2 {
+            m_off = last_m_off;
+        } else {
+            m_off = (m_off - 3) * 256 + u32::from(*src.get(ilen)?);
+            ilen += 1;
+            if m_off == u32::MAX {
+                break;
+            }
+            m_off += 1;
+            last_m_off = m_off;
+        }
+
+        let mut m_len = getbit(&mut ilen)?;
+        m_len = m_len * 2 + getbit(&mut ilen)?;
+        if m_len == 0 {
+            m_len = 1;
+            loop {
+                m_len = m_len.checked_mul(2)?.checked_add(getbit(&mut ilen)?)?;
+                if getbit(&mut ilen)? == 1 {
+                    break;
+                }
+            }
+            m_len = m_len.checked_add(2)?;
+        }
+        if m_off > 0xd00 {
+            m_len = m_len.checked_add(1)?;
+        }
+        if m_off as usize > olen {
+            return None;
+        }
+        olen = olen.checked_add(m_len as usize)?.checked_add(1)?;
+    }
+
+    Some((olen, ilen))
+}
+
+/// decompress a NRV compressed buffer of untrusted origin into a newly allocated buffer
+///
+/// Unlike [decompress], which allocates `dst_capacity` bytes up front, this determines the
+/// decompressed size with [estimate_decompressed_size] first and allocates exactly that much, so
+/// a small input can never cause a large allocation. `max_size` only limits how large the
+/// decompressed data may be: if it is larger, this will return
+/// `Err(UclErrorKind::OutputOverrun)`. If `src` is not a complete nrv2b stream, this will
+/// return `Err(UclErrorKind::InvalidArgument)`. In both cases nothing is allocated.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+///
+/// ```
+/// let src = b"\x92\xa5\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff";
+/// assert_eq!(uclcli::decompress_bounded(src, u32::MAX).unwrap().capacity(), 1024);
+/// assert_eq!(uclcli::decompress_bounded(src, 1023), Err(uclcli::UclErrorKind::OutputOverrun));
+/// assert_eq!(uclcli::decompress_bounded(&src[..8], u32::MAX), Err(uclcli::UclErrorKind::InvalidArgument));
+/// ```
+pub fn decompress_bounded(
+    src: &[u8],
+    max_size: u32,
+) -> core::result::Result<Vec<u8>, UclErrorKind> {
+    match estimate_decompressed_size(src) {
+        Some(size) if size <= max_size as usize => decompress(src, size as u32),
+        Some(_) => Err(UclErrorKind::OutputOverrun),
+        None => Err(UclErrorKind::InvalidArgument),
+    }
+}
+
+/// decompress a NRV compressed buffer of unknown decompressed size into a newly allocated buffer
+///
+/// The decompressed size is determined with [estimate_decompressed_size] first. If that fails,
+/// decompression is attempted with a buffer of `initial_capacity` bytes. Every time this
+/// results in `Err(UclErrorKind::OutputOverrun)`, the capacity is doubled and decompression is
+/// retried, up to a capacity of `u32::MAX` bytes. If decompression still overruns at that size,
+/// this will return `Err(UclErrorKind::OutputOverrun)`.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+///
+/// ```
+/// assert_eq!(uclcli::decompress_auto(b"\x92\xa5\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff", 16).unwrap(), [0xa5u8; 1024]);
+/// ```
+pub fn decompress_auto(
+    src: &[u8],
+    initial_capacity: u32,
+) -> core::result::Result<Vec<u8>, UclErrorKind> {
+    if let Some(size) = estimate_decompressed_size(src).and_then(|n| n.try_into().ok()) {
+        return decompress(src, size);
+    }
+
+    let mut capacity = initial_capacity.max(1);
+    loop {
+        match decompress(src, capacity) {
+            Err(UclErrorKind::OutputOverrun) if capacity < u32::MAX => {
+                capacity = capacity.saturating_mul(2);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// decompress a NRV compressed buffer into a writer
+///
+/// If `src` is a framed stream as created by [frame::compress_stream], every block is written
+/// to `out` as soon as it is decompressed, and `dst_capacity` is ignored. Otherwise, `src` is
+/// decompressed like [decompress] and written to `out` in one go. Errors writing to `out` are
+/// reported as `Err(UclErrorKind::Io(_))`.
+/// If decompression succeeded, this will return the number of bytes written to `out`.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+///
+/// ```
+/// let mut out = Vec::new();
+/// let src = b"\x92\xa5\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff";
+/// assert_eq!(uclcli::decompress_to_writer(src, 1024, &mut out), Ok(1024));
+/// assert_eq!(out, [0xa5u8; 1024].as_ref());
+/// ```
+#[cfg(feature = "std")]
+pub fn decompress_to_writer<W: std::io::Write>(
+    src: &[u8],
+    dst_capacity: u32,
+    out: &mut W,
+) -> core::result::Result<u64, UclErrorKind> {
+    if src.starts_with(&frame::STREAM_MAGIC) {
+        return Ok(frame::decompress_stream(&mut &src[..], out)?);
+    }
+
+    let dst = decompress(src, dst_capacity)?;
+    out.write_all(&dst)?;
+    Ok(dst.len() as u64)
+}
+
+/// Determine the overhead required by [decompress_in_place].
+///
+/// citing from libucl's README, for in-place decompression
+///
+/// > the compressed data must be at the end of the buffer, and the buffer must be
+/// > large enough to hold the decompressed data plus an overhead of
+/// >
+/// >   overhead = decompressed_block_size / 8 + 256
+pub const fn in_place_decompression_overhead(decompressed_len: usize) -> usize {
+    decompressed_len / 8 + 256
+}
+
+/// decompress a NRV compressed buffer in place
+///
+/// `buf[..compressed_len]` must hold the compressed data. It is moved to the end of `buf` and
+/// decompressed into the start of `buf`, overlapping the compressed data, so no second buffer
+/// is required. Up to `buf.len() - overhead` decompressed bytes are accepted, see
+/// [in_place_decompression_overhead] for a suitable `overhead`.
+///
+/// Before any data is decompressed, libucl checks that the decompressed data will never
+/// overwrite compressed data that has not been read yet. If it would, this returns
+/// `Err(UclErrorKind::OverlapOverrun)` and `buf` holds the compressed data at its end. If
+/// `compressed_len` is 0, this will return `Err(UclErrorKind::InvalidArgument)`, as no valid
+/// stream is empty. If decompression succeeded, this will return the number of usable bytes at
+/// the start of `buf`.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+///
+/// ```
+/// let compressed = b"\x92\xa5\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff";
+/// let overhead = uclcli::in_place_decompression_overhead(1024);
+///
+/// let mut buf = vec![0u8; 1024 + overhead];
+/// buf[..compressed.len()].copy_from_slice(compressed);
+///
+/// assert_eq!(uclcli::decompress_in_place(&mut buf, compressed.len(), overhead), Ok(1024));
+/// assert_eq!(&buf[..1024], [0xa5u8; 1024].as_ref());
+/// ```
+pub fn decompress_in_place(
+    buf: &mut [u8],
+    compressed_len: usize,
+    overhead: usize,
+) -> core::result::Result<u32, UclErrorKind> {
+    ensure_init();
+
+    // even a stream of nothing takes 8 bytes
+    if compressed_len == 0 || compressed_len > buf.len() || overhead > buf.len() {
+        return Err(UclErrorKind::InvalidArgument);
+    }
+    let buf_len: u32 = match buf.len().try_into() {
+        Ok(v) => v,
+        Err(_) => return Err(UclErrorKind::DstTooLarge),
+    };
+    let dst_capacity = buf_len - overhead as u32;
+    let src_len = compressed_len as u32;
+    let src_off = buf_len - src_len;
+
+    buf.copy_within(..compressed_len, src_off as usize);
+
+    let mut dst_len = dst_capacity;
+    let res = unsafe {
+        ucl_nrv2b_test_overlap_8(
+            buf.as_ptr(),
+            src_off,
+            src_len,
+            &mut dst_len,
+            ptr::null_mut(),
+        )
+    };
+    if res != 0 {
+        return Err(UclErrorKind::from(res));
+    }
+
+    let mut dst_len = dst_capacity;
+    let ptr = buf.as_mut_ptr();
+    let res = unsafe {
+        ucl_nrv2b_decompress_safe_8(
+            ptr.add(src_off as usize),
+            src_len,
+            ptr,
+            &mut dst_len,
+            ptr::null_mut(),
+        )
+    };
+    // report a bogus length as an error instead of panicking
+    match res {
+        0 if dst_len <= dst_capacity => Ok(dst_len),
+        0 => Err(UclErrorKind::OutputOverrun),
+        _ => Err(UclErrorKind::from(res)),
+    }
+}
+
+/// Determine the destination buffer size requirement for [compress_into_buffer].
+///
+/// citing from libucl's README:
+///
+/// > UCL will expand non-compressible data by a little amount. I suggest
+/// > using this formula for a worst-case expansion calculation:
+/// >
+/// >   output_block_size = input_block_size + (input_block_size / 8) + 256
+pub const fn minimum_compression_buffer_size(src_len: usize) -> usize {
+    src_len + (src_len / 8) + 256
+}
+
+/// Like [minimum_compression_buffer_size], but as the `u32` length libucl works with.
+///
+/// If the required size exceeds `u32::MAX`, this will return `Err(UclErrorKind::DstTooLarge)`.
+///
+/// ```
+/// assert_eq!(uclcli::minimum_compression_buffer_size_u32(1024), Ok(1408));
+/// assert_eq!(
+///     uclcli::minimum_compression_buffer_size_u32(u32::MAX as usize),
+///     Err(uclcli::UclErrorKind::DstTooLarge)
+/// );
+/// ```
+pub fn minimum_compression_buffer_size_u32(
+    src_len: usize,
+) -> core::result::Result<u32, UclErrorKind> {
+    src_len
+        .checked_add(src_len / 8)
+        .and_then(|n| n.checked_add(256))
+        .and_then(|n| n.try_into().ok())
+        .ok_or(UclErrorKind::DstTooLarge)
+}
+
+/// compression level used by [compress] and [compress_into_buffer]
+pub const DEFAULT_LEVEL: u8 = 6;
+/// lowest supported compression level, fastest
+pub const MIN_LEVEL: u8 = 1;
+/// highest supported compression level, smallest output
+pub const MAX_LEVEL: u8 = 10;
+
+/// SAFETY: dst_capacity must be >= minimum_compressed_buffer_size(src.len())
+///
+/// If `cb` is set, its callback must not unwind, see [progress_trampoline].
+unsafe fn compress_ptr(
+    src: &[u8],
+    dst: *mut u8,
+    dst_capacity: u32,
+    level: u8,
+    cb: *mut ucl_progress_callback_t,
+) -> core::result::Result<u32, UclErrorKind> {
+    compress_ptr_using(
+        ucl_nrv2b_99_compress,
+        src,
+        dst,
+        dst_capacity,
+        level,
+        cb,
+        ptr::null(),
+    )
+}
+
+/// signature of libucl's compressors, e.g. `ucl_nrv2b_99_compress`
+pub(crate) type CompressFn = unsafe extern "C" fn(
+    *const u8,
+    ucl_uint,
+    *mut u8,
+    *mut ucl_uint,
+    *mut ucl_progress_callback_t,
+    c_int,
+    *const ucl_compress_config_t,
+    *mut ucl_uint,
+) -> c_int;
+
+/// like [compress_ptr], but using `compress_fn` instead of nrv2b and `conf` instead of the
+/// default compression config if not null
+unsafe fn compress_ptr_using(
+    compress_fn: CompressFn,
+    src: &[u8],
+    dst: *mut u8,
+    dst_capacity: u32,
+    level: u8,
+    cb: *mut ucl_progress_callback_t,
+    conf: *const ucl_compress_config_t,
+) -> core::result::Result<u32, UclErrorKind> {
+    ensure_init();
+
+    if !(MIN_LEVEL..=MAX_LEVEL).contains(&level) {
+        return Err(UclErrorKind::InvalidArgument);
+    }
+
+    let src_len = match src.len().try_into() {
+        Ok(v) => v,
+        Err(_) => return Err(UclErrorKind::SrcTooLarge),
+    };
+
+    let mut dst_len = dst_capacity;
+
+    let res = compress_fn(
+        src.as_ptr(),
+        src_len,
+        dst,
+        &mut dst_len,
+        cb,
+        level.into(),
+        conf,
+        ptr::null_mut(), /* no statistical output */
+    );
+    match res {
+        0 if dst_len <= dst_capacity => Ok(dst_len),
+        // libucl does not check the capacity while compressing, so it has already written past
+        // the end of `dst`. Callers size `dst` with [minimum_compression_buffer_size], so this is
+        // unreachable unless memory is corrupted, which must not be reported as recoverable.
+        0 => {
+            #[cfg(feature = "std")]
+            std::process::abort();
+            #[cfg(not(feature = "std"))]
+            panic!("libucl wrote past the end of the destination buffer");
+        }
+        _ => Err(UclErrorKind::from(res)),
+    }
+}
+
+/// NRV compress a buffer into another buffer.
+///
+/// If `dst` is not big enough to hold the compressed
+/// buffer, this will return `Err(UclErrorKind::DstTooSmall { needed, got })`, where `needed` is
+/// [minimum_compression_buffer_size] of the input, so the caller can resize `dst` and retry.
+/// If compression succeeded, this will return the number of usable bytes in `dst`.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+/// ```
+/// let src = [0; 1024];
+/// let mut dst = vec![0xffu8; uclcli::minimum_compression_buffer_size(src.len())];
+///
+/// let result = uclcli::compress_into_buffer(&src, &mut dst);
+/// assert_eq!(result, Ok(12));
+///
+/// let nb = result.unwrap() as usize;
+///
+/// assert_eq!(&dst[..nb], b"\x92\x00\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff");
+/// assert_eq!(&dst[nb..], &vec![0xffu8; dst.len() - nb]);
+///
+/// let mut small = [0u8; 16];
+/// assert_eq!(
+///     uclcli::compress_into_buffer(&src, &mut small),
+///     Err(uclcli::UclErrorKind::DstTooSmall { needed: 1408, got: 16 })
+/// );
+/// ```
+pub fn compress_into_buffer(src: &[u8], dst: &mut [u8]) -> core::result::Result<u32, UclErrorKind> {
+    compress_into_buffer_with_level(src, dst, DEFAULT_LEVEL)
+}
+
+/// NRV compress a buffer into another buffer, using the given compression level.
+///
+/// `level` must be between [MIN_LEVEL] and [MAX_LEVEL], otherwise this will return
+/// `Err(UclErrorKind::InvalidArgument)`. See [compress_into_buffer] for details.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+pub fn compress_into_buffer_with_level(
+    src: &[u8],
+    dst: &mut [u8],
+    level: u8,
+) -> core::result::Result<u32, UclErrorKind> {
+    let needed = minimum_compression_buffer_size(src.len());
+    if dst.len() < needed {
+        return Err(UclErrorKind::DstTooSmall {
+            needed,
+            got: dst.len(),
+        });
+    }
+
+    let dst_len = match dst.len().try_into() {
+        Ok(v) => v,
+        Err(_) => return Err(UclErrorKind::DstTooLarge),
+    };
+
+    unsafe { compress_ptr(src, dst.as_mut_ptr(), dst_len, level, ptr::null_mut()) }
+}
+
+/// Like [compress_into_buffer], but returning the number of usable bytes as `usize`.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+/// ```
+/// let src = [0; 1024];
+/// let mut dst = vec![0u8; uclcli::minimum_compression_buffer_size(src.len())];
+///
+/// assert_eq!(uclcli::compress_into_slice(&src, &mut dst), Ok(12usize));
+/// ```
+pub fn compress_into_slice(
+    src: &[u8],
+    dst: &mut [u8],
+) -> core::result::Result<usize, UclErrorKind> {
+    compress_into_buffer(src, dst).map(|nb| nb as usize)
+}
+
+/// Like [compress_into_buffer], but compressing into uninitialized memory.
+///
+/// This avoids zeroing `dst` first, e.g. for buffers from an arena allocator. libucl only
+/// writes to the beginning of `dst`, so if compression succeeded, the first `n` bytes of `dst`
+/// are initialized, where `n` is the returned length. The rest of `dst` is left as it was, so
+/// it may still be uninitialized. On error, none of `dst` is guaranteed to be initialized.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+/// ```
+/// use std::mem::MaybeUninit;
+///
+/// let src = [0; 1024];
+/// let mut dst = vec![MaybeUninit::<u8>::uninit(); uclcli::minimum_compression_buffer_size(src.len())];
+///
+/// let nb = uclcli::compress_into_uninit(&src, &mut dst).unwrap();
+/// // SAFETY: the first `nb` bytes were initialized by compression
+/// let compressed = unsafe { std::slice::from_raw_parts(dst.as_ptr() as *const u8, nb as usize) };
+/// assert_eq!(compressed, b"\x92\x00\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff");
+/// ```
+pub fn compress_into_uninit(
+    src: &[u8],
+    dst: &mut [MaybeUninit<u8>],
+) -> core::result::Result<u32, UclErrorKind> {
+    let needed = minimum_compression_buffer_size(src.len());
+    if dst.len() < needed {
+        return Err(UclErrorKind::DstTooSmall {
+            needed,
+            got: dst.len(),
+        });
+    }
+
+    let dst_len = match dst.len().try_into() {
+        Ok(v) => v,
+        Err(_) => return Err(UclErrorKind::DstTooLarge),
+    };
+
+    unsafe {
+        compress_ptr(
+            src,
+            dst.as_mut_ptr().cast(),
+            dst_len,
+            DEFAULT_LEVEL,
+            ptr::null_mut(),
+        )
+    }
+}
+
+/// NRV compress a buffer into a newly allocated buffer.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+/// ```
+/// let src = [0; 1024];
+///
+/// assert_eq!(uclcli::compress(&src).unwrap(), b"\x92\x00\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff");
+/// ```
+pub fn compress(src: &[u8]) -> core::result::Result<Vec<u8>, UclErrorKind> {
+    compress_with_level(src, DEFAULT_LEVEL)
+}
+
+/// NRV compress a buffer into a newly allocated buffer, using the given compression level.
+///
+/// `level` must be between [MIN_LEVEL] and [MAX_LEVEL], otherwise this will return
+/// `Err(UclErrorKind::InvalidArgument)`.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+/// ```
+/// let src = [0; 1024];
+///
+/// let compressed = uclcli::compress_with_level(&src, 1).unwrap();
+/// assert_eq!(uclcli::decompress(&compressed, 1024).unwrap(), src);
+/// assert_eq!(uclcli::compress_with_level(&src, 11), Err(uclcli::UclErrorKind::InvalidArgument));
+/// ```
+pub fn compress_with_level(src: &[u8], level: u8) -> core::result::Result<Vec<u8>, UclErrorKind> {
+    compress_using(ucl_nrv2b_99_compress, src, level)
+}
+
+/// like [compress_with_level], but using `compress_fn` instead of nrv2b
+pub(crate) fn compress_using(
+    compress_fn: CompressFn,
+    src: &[u8],
+    level: u8,
+) -> core::result::Result<Vec<u8>, UclErrorKind> {
+    compress_using_config(compress_fn, src, level, &CompressConfig::default())
+}
+
+/// like [compress_with_config], but using `compress_fn` instead of nrv2b
+fn compress_using_config(
+    compress_fn: CompressFn,
+    src: &[u8],
+    level: u8,
+    config: &CompressConfig,
+) -> core::result::Result<Vec<u8>, UclErrorKind> {
+    let capacity = minimum_compression_buffer_size(src.len());
+    let mut dst = Vec::with_capacity(capacity);
+
+    let dst_len = match capacity.try_into() {
+        Ok(v) => v,
+        Err(_) => return Err(UclErrorKind::DstTooLarge),
+    };
+
+    let conf = config.to_ffi();
+    let conf = conf.as_ref().map_or(ptr::null(), |conf| conf as *const _);
+    unsafe {
+        let new_length = compress_ptr_using(
+            compress_fn,
+            src,
+            dst.as_mut_ptr(),
+            dst_len,
+            level,
+            ptr::null_mut(),
+            conf,
+        )?;
+        dst.set_len(new_length as usize);
+    }
+    Ok(dst)
+}
+
+/// Settings of libucl's compressor beyond the compression level, see [compress_with_config].
+///
+/// These mirror the fields of libucl's `ucl_compress_config_t` that affect the format of the
+/// compressed data, for decompressors which expect particular settings. Every setting left
+/// unset keeps libucl's default, and [CompressConfig::default] leaves all of them unset, which
+/// compresses exactly like [compress_with_level].
+///
+/// ```
+/// use uclcli::{BitBuffer, CompressConfig};
+///
+/// let config = CompressConfig::new().bit_buffer(BitBuffer::Le32).max_offset(0x10000);
+/// let compressed = uclcli::compress_with_config(&[0xa5; 1024], 6, &config).unwrap();
+/// assert_eq!(uclcli::decompress_with_bit_buffer(&compressed, 1024, BitBuffer::Le32).unwrap(), [0xa5u8; 1024]);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CompressConfig {
+    bit_buffer: Option<BitBuffer>,
+    max_offset: Option<u32>,
+    max_match: Option<u32>,
+}
+
+impl CompressConfig {
+    /// create a config with all settings at libucl's defaults
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// pack the bits controlling decompression as given, `bb_size` and `bb_endian` in libucl
+    ///
+    /// The result must be decompressed with the same packing, see [decompress_with_bit_buffer].
+    pub fn bit_buffer(mut self, bit_buffer: BitBuffer) -> Self {
+        self.bit_buffer = Some(bit_buffer);
+        self
+    }
+
+    /// limit how far back matches may refer, e.g. to the window size of another decompressor
+    pub fn max_offset(mut self, max_offset: u32) -> Self {
+        self.max_offset = Some(max_offset);
+        self
+    }
+
+    /// limit the length of a single match
+    pub fn max_match(mut self, max_match: u32) -> Self {
+        self.max_match = Some(max_match);
+        self
+    }
+
+    /// the config to pass to libucl, or `None` to pass none at all
+    fn to_ffi(self) -> Option<ucl_compress_config_t> {
+        if self == Self::default() {
+            return None;
+        }
+        // libucl treats all bits set as "use the default", like it does without a config
+        let bb_size = match self.bit_buffer {
+            Some(BitBuffer::Bits8) => 8,
+            Some(BitBuffer::Le16) => 16,
+            Some(BitBuffer::Le32) => 32,
+            None => -1,
+        };
+        Some(ucl_compress_config_t {
+            bb_endian: if self.bit_buffer.is_some() { 0 } else { -1 },
+            bb_size,
+            max_offset: self.max_offset.unwrap_or(ucl_uint::MAX),
+            max_match: self.max_match.unwrap_or(ucl_uint::MAX),
+            s_level: -1,
+            h_level: -1,
+            p_level: -1,
+            c_flags: -1,
+            m_size: ucl_uint::MAX,
+        })
+    }
+}
+
+/// NRV compress a buffer at `level` with the given [CompressConfig].
+///
+/// `level` must be between [MIN_LEVEL] and [MAX_LEVEL], otherwise this will return
+/// `Err(UclErrorKind::InvalidArgument)`, as will libucl for settings it does not support.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+/// ```
+/// use uclcli::CompressConfig;
+///
+/// let compressed = uclcli::compress_with_config(&[0; 1024], 6, &CompressConfig::default()).unwrap();
+/// assert_eq!(compressed, b"\x92\x00\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff");
+/// ```
+pub fn compress_with_config(
+    src: &[u8],
+    level: u8,
+    config: &CompressConfig,
+) -> core::result::Result<Vec<u8>, UclErrorKind> {
+    compress_using_config(ucl_nrv2b_99_compress, src, level, config)
+}
+
+/// NRV compress a buffer into `out`, reusing its allocation.
+///
+/// `out` is cleared and grown to [minimum_compression_buffer_size] if necessary, so compressing
+/// many buffers into the same `Vec` only allocates when a larger input comes along. On success,
+/// `out` holds exactly the compressed data. On error, `out` is empty.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+/// ```
+/// let mut out = Vec::new();
+///
+/// uclcli::compress_into_vec(&[0; 1024], &mut out).unwrap();
+/// assert_eq!(out, b"\x92\x00\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff");
+///
+/// uclcli::compress_into_vec(&[], &mut out).unwrap();
+/// assert_eq!(out, b"\x00\x00\x00\x00\x00\x04\x80\xff");
+/// ```
+pub fn compress_into_vec(src: &[u8], out: &mut Vec<u8>) -> core::result::Result<(), UclErrorKind> {
+    compress_vec(src, out, DEFAULT_LEVEL)
+}
+
+/// compress into `out` at `level`, shared by [compress_into_vec] and [Compressor]
+fn compress_vec(
+    src: &[u8],
+    out: &mut Vec<u8>,
+    level: u8,
+) -> core::result::Result<(), UclErrorKind> {
+    out.clear();
+    compress_append(src, out, level).map(|_| ())
+}
+
+/// NRV compress a buffer at `level`, appending the compressed data to `out`.
+///
+/// Reserves [minimum_compression_buffer_size] bytes after the current end of `out` and
+/// compresses into them, so many blocks can be compressed into one growing `Vec` without an
+/// allocation per block. On success, `out` is extended by the compressed data and its length is
+/// returned. On error, `out` keeps its previous contents.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+/// ```
+/// let mut out = b"header".to_vec();
+///
+/// let nb = uclcli::compress_append(&[0; 1024], &mut out, 6).unwrap();
+/// assert_eq!(nb, 12);
+/// assert_eq!(out, b"header\x92\x00\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff");
+/// ```
+pub fn compress_append(
+    src: &[u8],
+    out: &mut Vec<u8>,
+    level: u8,
+) -> core::result::Result<u32, UclErrorKind> {
+    let capacity = minimum_compression_buffer_size(src.len());
+    let dst_len = match capacity.try_into() {
+        Ok(v) => v,
+        Err(_) => return Err(UclErrorKind::DstTooLarge),
+    };
+    out.reserve(capacity);
+
+    unsafe {
+        let len = out.len();
+        let new_length = compress_ptr(
+            src,
+            out.as_mut_ptr().add(len),
+            dst_len,
+            level,
+            ptr::null_mut(),
+        )?;
+        out.set_len(len + new_length as usize);
+        Ok(new_length)
+    }
+}
+
+/// NRV compress a buffer, unless that does not make it any smaller.
+///
+/// Returns `(true, compressed)` if the compressed data is smaller than `src`, and
+/// `(false, src.to_vec())` otherwise, so incompressible data can be stored verbatim instead of
+/// being expanded. [compress_framed] stores incompressible data the same way.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+/// ```
+/// assert_eq!(uclcli::maybe_compress(b"abc").unwrap(), (false, b"abc".to_vec()));
+///
+/// let (compressed, data) = uclcli::maybe_compress(&[0; 1024]).unwrap();
+/// assert!(compressed);
+/// assert_eq!(uclcli::decompress(&data, 1024).unwrap(), [0; 1024]);
+/// ```
+pub fn maybe_compress(src: &[u8]) -> core::result::Result<(bool, Vec<u8>), UclErrorKind> {
+    maybe_compress_with_level(src, DEFAULT_LEVEL)
+}
+
+/// like [maybe_compress], but using the given compression level
+///
+/// `level` must be between [MIN_LEVEL] and [MAX_LEVEL], otherwise this will return
+/// `Err(UclErrorKind::InvalidArgument)`.
+pub fn maybe_compress_with_level(
+    src: &[u8],
+    level: u8,
+) -> core::result::Result<(bool, Vec<u8>), UclErrorKind> {
+    let compressed = compress_with_level(src, level)?;
+    if compressed.len() < src.len() {
+        Ok((true, compressed))
+    } else {
+        Ok((false, src.to_vec()))
+    }
+}
+
+/// NRV compress everything read from `src` into a framed stream written to `dst`.
+///
+/// This is [frame::compress_stream] with errors reported as [UclErrorKind]. Input is read and
+/// compressed block by block, so it does not need to fit into memory. `level` must be between
+/// [MIN_LEVEL] and [MAX_LEVEL], otherwise this will return `Err(UclErrorKind::InvalidArgument)`.
+/// If compression succeeded, this will return the number of compressed bytes written to `dst`.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+/// ```
+/// let mut compressed = Vec::new();
+/// let nb = uclcli::compress_from_reader(&mut &[0u8; 1024][..], &mut compressed, 6).unwrap();
+/// assert_eq!(nb, compressed.len() as u64);
+///
+/// let mut decompressed = Vec::new();
+/// uclcli::decompress_to_writer(&compressed, 0, &mut decompressed).unwrap();
+/// assert_eq!(decompressed, [0u8; 1024].as_ref());
+/// ```
+#[cfg(feature = "std")]
+pub fn compress_from_reader<R: std::io::Read, W: std::io::Write>(
+    src: &mut R,
+    dst: &mut W,
+    level: u8,
+) -> core::result::Result<u64, UclErrorKind> {
+    let mut counter = io::CountingWriter {
+        inner: dst,
+        count: 0,
+    };
+    frame::compress_stream(src, &mut counter, level)?;
+    Ok(counter.count)
+}
+
+/// NRV compress a buffer at every level, returning the smallest result and its level.
+///
+/// If several levels produce the same size, the lowest of them is returned.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+/// ```
+/// let src = b"hello hello hello hello";
+///
+/// let (compressed, level) = uclcli::compress_best(src).unwrap();
+/// assert!(level >= uclcli::MIN_LEVEL && level <= uclcli::MAX_LEVEL);
+/// assert_eq!(uclcli::decompress(&compressed, 23).unwrap(), src);
+/// ```
+pub fn compress_best(src: &[u8]) -> core::result::Result<(Vec<u8>, u8), UclErrorKind> {
+    let mut compressor = Compressor::with_level(MIN_LEVEL);
+    let mut best = compressor.compress(src)?;
+    let mut best_level = MIN_LEVEL;
+
+    for level in MIN_LEVEL + 1..=MAX_LEVEL {
+        compressor.level = level;
+        let compressed = compressor.compress_into(src)?;
+        if compressed.len() < best.len() {
+            best.clear();
+            best.extend_from_slice(compressed);
+            best_level = level;
+        }
+    }
+    Ok((best, best_level))
+}
+
+/// Reusable compression state, amortizing allocations across many compression calls.
+///
+/// libucl's nrv2b compressor keeps no state between calls that could be reused, but the output
+/// buffer can be: [Compressor::compress_into] compresses into an internal buffer that is only
+/// ever grown, so compressing many small blocks in a loop does not allocate at all.
+///
+/// ```
+/// let mut compressor = uclcli::Compressor::new();
+/// for _ in 0..3 {
+///     assert_eq!(compressor.compress_into(&[0; 1024]).unwrap(), b"\x92\x00\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff");
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Compressor {
+    level: u8,
+    buf: Vec<u8>,
+}
+
+impl Default for Compressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compressor {
+    /// create a compressor using [DEFAULT_LEVEL]
+    pub fn new() -> Self {
+        Self::with_level(DEFAULT_LEVEL)
+    }
+
+    /// create a compressor using the given compression level
+    ///
+    /// `level` must be between [MIN_LEVEL] and [MAX_LEVEL], otherwise compression will return
+    /// `Err(UclErrorKind::InvalidArgument)`.
+    pub fn with_level(level: u8) -> Self {
+        Compressor {
+            level,
+            buf: Vec::new(),
+        }
+    }
+
+    /// NRV compress a buffer into the internal buffer, returning the compressed data
+    ///
+    /// The returned slice is valid until the next call on this compressor.
+    ///
+    /// # Panics
+    /// If libucl cannot be initialized, this function will panic, see [ucl_init].
+    pub fn compress_into(&mut self, src: &[u8]) -> core::result::Result<&[u8], UclErrorKind> {
+        compress_vec(src, &mut self.buf, self.level)?;
+        Ok(&self.buf)
+    }
+
+    /// NRV compress a buffer into a newly allocated buffer of exactly the compressed size
+    ///
+    /// # Panics
+    /// If libucl cannot be initialized, this function will panic, see [ucl_init].
+    pub fn compress(&mut self, src: &[u8]) -> core::result::Result<Vec<u8>, UclErrorKind> {
+        self.compress_into(src).map(<[u8]>::to_vec)
+    }
+}
+
+#[cfg(feature = "std")]
+struct ProgressContext<F> {
+    cb: F,
+    total: u32,
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+/// called by libucl during compression, catching any panic of the user callback
+#[cfg(feature = "std")]
+unsafe extern "C" fn progress_trampoline<F: FnMut(u32, u32)>(
+    textsize: c_uint,
+    _codesize: c_uint,
+    _state: c_int,
+    user: *mut c_void,
+) {
+    let ctx = &mut *(user as *mut ProgressContext<F>);
+    if ctx.panic.is_some() {
+        return;
+    }
+    // unwinding into libucl is undefined behavior, so keep the panic until compression returns
+    let total = ctx.total;
+    let cb = &mut ctx.cb;
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| cb(textsize, total))) {
+        ctx.panic = Some(payload);
+    }
+}
+
+/// NRV compress a buffer into a newly allocated buffer, reporting progress to `cb`.
+///
+/// `cb` is called periodically with the number of input bytes processed so far and the total
+/// number of input bytes. `level` must be between [MIN_LEVEL] and [MAX_LEVEL], otherwise this
+/// will return `Err(UclErrorKind::InvalidArgument)`.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init]. If `cb` panics,
+/// the panic is propagated once libucl has returned.
+/// ```
+/// let src = [0; 100000];
+/// let mut last = 0;
+///
+/// let compressed = uclcli::compress_with_progress(&src, 6, |done, total| {
+///     assert!(done >= last && done <= total);
+///     last = done;
+/// }).unwrap();
+/// assert_eq!(uclcli::decompress(&compressed, 100000).unwrap(), src);
+/// ```
+#[cfg(feature = "std")]
+pub fn compress_with_progress<F: FnMut(u32, u32)>(
+    src: &[u8],
+    level: u8,
+    cb: F,
+) -> core::result::Result<Vec<u8>, UclErrorKind> {
+    let capacity = minimum_compression_buffer_size(src.len());
+    let mut dst = Vec::with_capacity(capacity);
+
+    let dst_len = match capacity.try_into() {
+        Ok(v) => v,
+        Err(_) => return Err(UclErrorKind::DstTooLarge),
+    };
+
+    let mut ctx = ProgressContext {
+        cb,
+        // src_len is checked in compress_ptr
+        total: src.len() as u32,
+        panic: None,
+    };
+    let mut callback = ucl_progress_callback_t {
+        callback: Some(progress_trampoline::<F>),
+        user: &mut ctx as *mut ProgressContext<F> as *mut c_void,
+    };
+
+    let result = unsafe { compress_ptr(src, dst.as_mut_ptr(), dst_len, level, &mut callback) };
+    if let Some(payload) = ctx.panic {
+        panic::resume_unwind(payload);
+    }
+
+    unsafe {
+        dst.set_len(result? as usize);
+    }
+    Ok(dst)
+}
+
+/// Statistics about a single compression run, as returned by [compress_with_stats].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CompressStats {
+    /// number of uncompressed bytes that went into the compressor
+    pub input_len: usize,
+    /// number of compressed bytes produced
+    pub output_len: usize,
+    /// `output_len / input_len`, i.e. smaller is better. This is infinite for empty input.
+    pub ratio: f64,
+}
+
+impl CompressStats {
+    /// statistics for compressing `input_len` bytes into `output_len` bytes
+    ///
+    /// ```
+    /// let stats = uclcli::CompressStats::new(1024, 12);
+    /// assert_eq!(stats.ratio, 12.0 / 1024.0);
+    /// ```
+    pub fn new(input_len: usize, output_len: usize) -> Self {
+        CompressStats {
+            input_len,
+            output_len,
+            ratio: output_len as f64 / input_len as f64,
+        }
+    }
+}
+
+/// Formats the sizes and how well the input compressed, e.g.
+/// `1048576 -> 132480 bytes (7.9x, 87.4% saved)`.
+///
+/// ```
+/// let stats = uclcli::CompressStats::new(1048576, 132480);
+/// assert_eq!(stats.to_string(), "1048576 -> 132480 bytes (7.9x, 87.4% saved)");
+/// ```
+impl fmt::Display for CompressStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {} bytes", self.input_len, self.output_len)?;
+        if self.input_len > 0 && self.output_len > 0 {
+            write!(
+                f,
+                " ({:.1}x, {:.1}% saved)",
+                1.0 / self.ratio,
+                (1.0 - self.ratio) * 100.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// NRV compress a buffer into a newly allocated buffer, also returning [CompressStats].
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+/// ```
+/// let src = [0; 1024];
+///
+/// let (dst, stats) = uclcli::compress_with_stats(&src).unwrap();
+/// assert_eq!(dst.len(), 12);
+/// assert_eq!(stats.input_len, 1024);
+/// assert_eq!(stats.output_len, 12);
+/// assert_eq!(stats.ratio, 12.0 / 1024.0);
+/// ```
+pub fn compress_with_stats(
+    src: &[u8],
+) -> core::result::Result<(Vec<u8>, CompressStats), UclErrorKind> {
+    let dst = compress(src)?;
+    let stats = CompressStats::new(src.len(), dst.len());
+    Ok((dst, stats))
+}
+
+/// Update an Adler-32 checksum with `data`, using libucl's implementation.
+///
+/// Pass `1` as `prev` to start a new checksum. `data` may be of any size.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+/// ```
+/// assert_eq!(uclcli::adler32(1, b"abc"), 0x024d_0127);
+/// assert_eq!(uclcli::adler32(uclcli::adler32(1, b"a"), b"bc"), 0x024d_0127);
+/// ```
+pub fn adler32(prev: u32, data: &[u8]) -> u32 {
+    ensure_init();
+    data.chunks(c_uint::MAX as usize)
+        .fold(prev, |c, chunk| unsafe {
+            ucl_adler32(c, chunk.as_ptr(), chunk.len() as c_uint)
+        })
+}
+
+/// Update a CRC-32 checksum with `data`, using libucl's implementation.
+///
+/// Pass `0` as `prev` to start a new checksum. `data` may be of any size.
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [ucl_init].
+/// ```
+/// assert_eq!(uclcli::crc32(0, b"abc"), 0x3524_41c2);
+/// assert_eq!(uclcli::crc32(uclcli::crc32(0, b"a"), b"bc"), 0x3524_41c2);
+/// ```
+pub fn crc32(prev: u32, data: &[u8]) -> u32 {
+    ensure_init();
+    data.chunks(c_uint::MAX as usize)
+        .fold(prev, |c, chunk| unsafe {
+            ucl_crc32(c, chunk.as_ptr(), chunk.len() as c_uint)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Once;
+
+    use proptest::prelude::*;
+
+    use super::{
+        adler32, compress, compress_append, compress_best, compress_from_reader,
+        compress_into_buffer, compress_into_slice, compress_into_uninit, compress_into_vec,
+        compress_with_config, compress_with_level, compress_with_progress, compress_with_stats,
+        crc32, decompress, decompress_auto, decompress_bounded, decompress_in_place,
+        decompress_into_buffer, decompress_into_buffer_consumed, decompress_into_buffer_unchecked,
+        decompress_into_slice, decompress_into_uninit, decompress_raw, decompress_to_writer,
+        decompress_with_bit_buffer, decompress_with_capacity, estimate_decompressed_size,
+        in_place_decompression_overhead, is_compatible_version, library_version,
+        library_version_string, maybe_compress, minimum_compression_buffer_size,
+        minimum_compression_buffer_size_u32, reset_for_test, try_decompress_all, ucl_init,
+        ucl_init_checked, BitBuffer, CompressConfig, CompressStats, Compressor, DecompressAllError,
+        MaybeUninit, Ordering, UclErrorKind, INITIALIZED, MAX_LEVEL, MIN_LEVEL, UCL_VERSION,
+    };
+
+    /// initialize libucl once for all tests, failing with a clear message if it is unusable
+    fn setup() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| ucl_init_checked().expect("libucl cannot be initialized"));
+    }
+
+    #[test]
+    fn compress_buffer_nothing() {
+        setup();
+        let mut buf = [0xa5u8; 256];
+        assert_eq!(compress_into_buffer(&[], &mut buf).unwrap(), 8);
+        assert_eq!(&buf[..8], b"\x00\x00\x00\x00\x00\x04\x80\xff");
+        assert!(&buf[8..].iter().all(|b| *b == 0xa5));
+    }
+
+    #[test]
+    fn compress_buffer_too_small() {
+        setup();
+        let mut buf = [0xa5u8; 4];
+        assert_eq!(
+            compress_into_buffer(b"\xde\xad\xbe\xef", &mut buf).unwrap_err(),
+            UclErrorKind::DstTooSmall {
+                needed: 260,
+                got: 4
+            }
+        );
+        assert!(buf.iter().all(|b| *b == 0xa5));
+    }
+
+    #[test]
+    #[ignore = "allocates 4 GiB, run with --ignored"]
+    fn compress_buffer_too_big() {
+        setup();
+        let mut buf = vec![0u8; 4 * 1024 * 1024 * 1024];
+        assert_eq!(
+            compress_into_buffer(b"\xde\xad\xbe\xef", &mut buf).unwrap_err(),
+            UclErrorKind::DstTooLarge
+        );
+    }
+
+    #[test]
+    #[ignore = "allocates 4 GiB, run with --ignored"]
+    fn decompress_buffer_dst_too_big() {
+        setup();
+        let mut buf = vec![0u8; 4 * 1024 * 1024 * 1024];
+        assert_eq!(
+            decompress_into_buffer(b"\xde\xad\xbe\xef", &mut buf).unwrap_err(),
+            UclErrorKind::DstTooLarge
+        );
+    }
+
+    #[test]
+    #[ignore = "allocates 4 GiB, run with --ignored"]
+    fn decompress_buffer_src_too_big() {
+        setup();
+        let mut buf = vec![0u8; 4];
+        let input = vec![0u8; 4 * 1024 * 1024 * 1024];
+        assert_eq!(
+            decompress_into_buffer(&input, &mut buf).unwrap_err(),
+            UclErrorKind::SrcTooLarge
+        );
+    }
+
+    #[test]
+    fn decompress_buffer_nothing() {
+        setup();
+        let compressed = b"\x00\x00\x00\x00\x00\x04\x80\xff";
+        let mut buf = [0xa5u8; 8];
+        assert_eq!(
+            decompress_into_buffer(compressed.as_ref(), &mut buf).unwrap(),
+            0
+        );
+        assert!(buf.iter().all(|b| *b == 0xa5));
+    }
+
+    #[test]
+    fn decompress_buffer_8k_too_small() {
+        setup();
+        let compressed =
+            b"\x92\x00\xaa\xa8\xc9\x55\x54\x64\xaa\xaa\x32\x55\x55\x08\x00\x00\x00\x00\x00\x24\xff";
+        let mut buf = [0xa5u8; 8191];
+        assert_eq!(
+            decompress_into_buffer(compressed.as_ref(), &mut buf).unwrap_err(),
+            UclErrorKind::OutputOverrun
+        );
+    }
+
+    #[test]
+    #[ignore = "allocates 4 GiB, run with --ignored"]
+    fn decompress_src_too_big() {
+        setup();
+        let input = vec![0u8; 4 * 1024 * 1024 * 1024];
+        assert_eq!(
+            decompress(&input, 4).unwrap_err(),
+            UclErrorKind::SrcTooLarge
+        );
+    }
+
+    #[test]
+    fn decompress_nothing() {
+        setup();
+        let compressed = b"\x00\x00\x00\x00\x00\x04\x80\xff";
+        assert_eq!(decompress(compressed.as_ref(), 8).unwrap(), b"");
+    }
+
+    #[test]
+    fn decompress_8k_too_small() {
+        setup();
+        let compressed =
+            b"\x92\x00\xaa\xa8\xc9\x55\x54\x64\xaa\xaa\x32\x55\x55\x08\x00\x00\x00\x00\x00\x24\xff";
+        assert_eq!(
+            decompress(compressed.as_ref(), 8191).unwrap_err(),
+            UclErrorKind::OutputOverrun
+        );
+    }
+
+    #[test]
+    fn compress_into_vec_reuses_allocation() {
+        setup();
+        let mut out = Vec::new();
+        compress_into_vec(&[0; 4096], &mut out).unwrap();
+        let capacity = out.capacity();
+        let ptr = out.as_ptr();
+
+        compress_into_vec(&[0; 1024], &mut out).unwrap();
+        assert_eq!(out, compress(&[0; 1024]).unwrap());
+        assert_eq!(out.capacity(), capacity);
+        assert_eq!(out.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn compress_append_blocks() {
+        setup();
+        let first: Vec<u8> = (0..4096u32).map(|i| (i % 251) as u8).collect();
+        let second = [0x5a; 1000];
+        let mut out = Vec::new();
+        let n1 = compress_append(&first, &mut out, 6).unwrap() as usize;
+        let n2 = compress_append(&second, &mut out, 9).unwrap() as usize;
+        assert_eq!(out.len(), n1 + n2);
+        assert_eq!(out[..n1], compress_with_level(&first, 6).unwrap()[..]);
+        assert_eq!(out[n1..], compress_with_level(&second, 9).unwrap()[..]);
+
+        assert_eq!(
+            compress_append(&second, &mut out, 11).unwrap_err(),
+            UclErrorKind::InvalidArgument
+        );
+        assert_eq!(out.len(), n1 + n2);
+    }
+
+    #[test]
+    fn default_config_matches_null_config() {
+        setup();
+        let src: Vec<u8> = (0..100_000u32)
+            .map(|i| (i % 251) as u8 ^ (i / 4096) as u8)
+            .collect();
+        for level in MIN_LEVEL..=MAX_LEVEL {
+            assert_eq!(
+                compress_with_config(&src, level, &CompressConfig::default()).unwrap(),
+                compress_with_level(&src, level).unwrap()
+            );
+        }
+        assert_eq!(
+            compress_with_config(&[0; 1024], 6, &CompressConfig::new()).unwrap(),
+            b"\x92\x00\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff"
+        );
+    }
+
+    #[test]
+    fn config_roundtrip() {
+        setup();
+        let src: Vec<u8> = (0..100_000u32)
+            .map(|i| (i % 251) as u8 ^ (i / 4096) as u8)
+            .collect();
+        for bit_buffer in [BitBuffer::Bits8, BitBuffer::Le16, BitBuffer::Le32].iter() {
+            let config = CompressConfig::new().bit_buffer(*bit_buffer);
+            let compressed = compress_with_config(&src, 6, &config).unwrap();
+            assert_eq!(
+                decompress_with_bit_buffer(&compressed, 100_000, *bit_buffer).unwrap(),
+                src
+            );
+        }
+
+        let config = CompressConfig::new().max_offset(1024).max_match(16);
+        let compressed = compress_with_config(&src, 6, &config).unwrap();
+        assert_eq!(decompress(&compressed, 100_000).unwrap(), src);
+        assert_eq!(
+            compress_with_config(&src, 11, &config).unwrap_err(),
+            UclErrorKind::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn uninit_roundtrip() {
+        setup();
+        let src: Vec<u8> = (0..4096u32).map(|i| (i % 251) as u8).collect();
+        let mut compressed =
+            vec![MaybeUninit::uninit(); minimum_compression_buffer_size(src.len())];
+        let clen = compress_into_uninit(&src, &mut compressed).unwrap() as usize;
+        let compressed: Vec<u8> = compressed[..clen]
+            .iter()
+            .map(|b| unsafe { b.assume_init() })
+            .collect();
+        assert_eq!(compressed, compress(&src).unwrap());
+
+        let mut dst = vec![MaybeUninit::uninit(); src.len() + 100];
+        let dlen = decompress_into_uninit(&compressed, &mut dst).unwrap() as usize;
+        assert_eq!(dlen, src.len());
+        assert!(dst[..dlen]
+            .iter()
+            .zip(&src)
+            .all(|(b, s)| unsafe { b.assume_init() } == *s));
+
+        let mut small = [MaybeUninit::uninit(); 16];
+        assert_eq!(
+            decompress_into_uninit(&compressed, &mut small),
+            Err(UclErrorKind::OutputOverrun)
+        );
+    }
+
+    #[test]
+    fn decompress_bounded_allocates_exact_size() {
+        setup();
+        let compressed = compress(&[0x5a; 100_000]).unwrap();
+        let dst = decompress_bounded(&compressed, u32::MAX).unwrap();
+        assert_eq!(dst, [0x5a; 100_000].as_ref());
+        assert_eq!(dst.capacity(), 100_000);
+
+        assert_eq!(
+            decompress_bounded(&compressed, 99_999),
+            Err(UclErrorKind::OutputOverrun)
+        );
+        assert_eq!(
+            decompress_bounded(&compressed[..compressed.len() - 1], u32::MAX),
+            Err(UclErrorKind::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn stats_display() {
+        assert_eq!(
+            CompressStats::new(1024, 12).to_string(),
+            "1024 -> 12 bytes (85.3x, 98.8% saved)"
+        );
+        assert_eq!(
+            CompressStats::new(100, 108).to_string(),
+            "100 -> 108 bytes (0.9x, -8.0% saved)"
+        );
+        assert_eq!(CompressStats::new(0, 8).to_string(), "0 -> 8 bytes");
     }
 
     #[test]
-    fn compress_buffer_too_big() {
-        ucl_init();
-        let mut buf = vec![0u8; 4 * 1024 * 1024 * 1024];
+    fn minimum_buffer_size_u32_bounds() {
+        // largest input whose bound still fits into u32
+        let max = ((u32::MAX as u64 - 256) * 8 / 9) as usize;
         assert_eq!(
-            compress_into_buffer(b"\xde\xad\xbe\xef", &mut buf).unwrap_err(),
-            UclErrorKind::DstTooLarge
+            minimum_compression_buffer_size_u32(max).map(|n| n as usize),
+            Ok(minimum_compression_buffer_size(max))
+        );
+        assert_eq!(
+            minimum_compression_buffer_size_u32(max + 1),
+            Err(UclErrorKind::DstTooLarge)
+        );
+        assert_eq!(
+            minimum_compression_buffer_size_u32(usize::MAX),
+            Err(UclErrorKind::DstTooLarge)
         );
     }
 
     #[test]
-    fn decompress_buffer_dst_too_big() {
-        ucl_init();
-        let mut buf = vec![0u8; 4 * 1024 * 1024 * 1024];
+    fn decompress_buffer_unchecked_matches_checked() {
+        setup();
+        let compressed =
+            b"\x92\x00\xaa\xa8\xc9\x55\x54\x64\xaa\xaa\x32\x55\x55\x08\x00\x00\x00\x00\x00\x24\xff";
+        let mut checked = [0xa5u8; 8192];
+        let mut unchecked = [0x5au8; 8192];
         assert_eq!(
-            decompress_into_buffer(b"\xde\xad\xbe\xef", &mut buf).unwrap_err(),
-            UclErrorKind::DstTooLarge
+            decompress_into_buffer_unchecked(compressed.as_ref(), &mut unchecked),
+            decompress_into_buffer(compressed.as_ref(), &mut checked)
+        );
+        assert_eq!(checked, unchecked);
+        assert_eq!(
+            decompress_into_buffer_unchecked(compressed.as_ref(), &mut unchecked[..8191])
+                .unwrap_err(),
+            UclErrorKind::OutputOverrun
         );
     }
 
     #[test]
-    fn decompress_buffer_src_too_big() {
-        ucl_init();
-        let mut buf = vec![0u8; 4];
-        let input = vec![0u8; 4 * 1024 * 1024 * 1024];
+    fn decompress_trailing_data() {
+        setup();
+        let mut compressed = compress(&[0xa5; 1024]).unwrap();
+        compressed.extend_from_slice(&compress(b"second file").unwrap());
         assert_eq!(
-            decompress_into_buffer(&input, &mut buf).unwrap_err(),
-            UclErrorKind::SrcTooLarge
+            decompress(&compressed, 4096).unwrap_err(),
+            UclErrorKind::InputNotConsumed
+        );
+        assert_eq!(
+            decompress_auto(&compressed, 16).unwrap_err(),
+            UclErrorKind::InputNotConsumed
         );
     }
 
     #[test]
-    fn decompress_buffer_nothing() {
-        ucl_init();
-        let compressed = b"\x00\x00\x00\x00\x00\x04\x80\xff";
-        let mut buf = [0xa5u8; 8];
+    fn estimate_size_matches_decompression() {
+        setup();
+        let src: Vec<u8> = (0..100_000u32)
+            .map(|i| (i % 251) as u8 ^ (i / 4096) as u8)
+            .collect();
+        for level in MIN_LEVEL..=MAX_LEVEL {
+            let compressed = compress_with_level(&src, level).unwrap();
+            assert_eq!(estimate_decompressed_size(&compressed), Some(src.len()));
+        }
         assert_eq!(
-            decompress_into_buffer(compressed.as_ref(), &mut buf).unwrap(),
-            0
+            estimate_decompressed_size(b"\x00\x00\x00\x00\x00\x04\x80\xff"),
+            Some(0)
         );
-        assert!(buf.iter().all(|b| *b == 0xa5));
     }
 
     #[test]
-    fn decompress_buffer_8k_too_small() {
-        ucl_init();
-        let compressed =
-            b"\x92\x00\xaa\xa8\xc9\x55\x54\x64\xaa\xaa\x32\x55\x55\x08\x00\x00\x00\x00\x00\x24\xff";
-        let mut buf = [0xa5u8; 8191];
+    fn estimate_size_invalid() {
+        let compressed = b"\x92\xa5\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff";
+        assert_eq!(estimate_decompressed_size(b""), None);
+        assert_eq!(estimate_decompressed_size(&compressed[..11]), None);
+        assert_eq!(estimate_decompressed_size(b"\xde\xad\xbe\xef"), None);
+
+        let mut trailing = compressed.to_vec();
+        trailing.push(0);
+        assert_eq!(estimate_decompressed_size(&trailing), None);
+    }
+
+    #[test]
+    fn decompress_consumed_concatenated() {
+        setup();
+        let first: Vec<u8> = (0..4096u32).map(|i| (i % 251) as u8).collect();
+        let second = [0x5a; 1000];
+        let mut src = compress(&first).unwrap();
+        let first_len = src.len();
+        src.extend_from_slice(&compress(&second).unwrap());
+
+        let mut buf = vec![0u8; 8192];
+        let (nb, consumed) = decompress_into_buffer_consumed(&src, &mut buf).unwrap();
+        assert_eq!((nb as usize, consumed as usize), (first.len(), first_len));
+        assert_eq!(&buf[..nb as usize], &first[..]);
+
+        let rest = &src[consumed as usize..];
+        let (nb, consumed) = decompress_into_buffer_consumed(rest, &mut buf).unwrap();
+        assert_eq!((nb as usize, consumed as usize), (second.len(), rest.len()));
+        assert_eq!(&buf[..nb as usize], &second[..]);
+
         assert_eq!(
-            decompress_into_buffer(compressed.as_ref(), &mut buf).unwrap_err(),
+            decompress_into_buffer_consumed(&src[..first_len - 1], &mut buf).unwrap_err(),
+            UclErrorKind::InputOverrun
+        );
+    }
+
+    #[test]
+    fn decompress_raw_pointers() {
+        setup();
+        let compressed = compress(&[0x5a; 4096]).unwrap();
+        let mut dst = vec![0u8; 4096];
+        let nb = unsafe {
+            decompress_raw(
+                compressed.as_ptr(),
+                compressed.len() as u32,
+                dst.as_mut_ptr(),
+                4096,
+            )
+        };
+        assert_eq!(nb, Ok(4096));
+        assert_eq!(dst, [0x5a; 4096]);
+
+        let nb = unsafe {
+            decompress_raw(
+                compressed.as_ptr(),
+                compressed.len() as u32,
+                core::ptr::null_mut(),
+                0,
+            )
+        };
+        assert_eq!(nb, Err(UclErrorKind::OutputOverrun));
+        let nb = unsafe { decompress_raw(core::ptr::null(), 0, dst.as_mut_ptr(), 4096) };
+        assert_eq!(nb, Err(UclErrorKind::InvalidArgument));
+    }
+
+    #[test]
+    fn bit_buffer_packings() {
+        setup();
+        // the same 1024 bytes of 0xa5 and the same empty stream in every packing
+        let streams: [(BitBuffer, &[u8], &[u8]); 3] = [
+            (
+                BitBuffer::Bits8,
+                b"\x92\xa5\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff",
+                b"\x00\x00\x00\x00\x00\x04\x80\xff",
+            ),
+            (
+                BitBuffer::Le16,
+                b"\xaa\x92\xa5\x00\xa1\x00\x00\x00\x00\x80\x04\xff",
+                b"\x00\x00\x00\x00\x04\x00\x00\x80\xff",
+            ),
+            (
+                BitBuffer::Le32,
+                b"\x00\xa1\xaa\x92\xa5\x00\x00\x00\x00\x00\x00\x80\x04\xff",
+                b"\x00\x00\x00\x00\x00\x80\x04\x00\xff",
+            ),
+        ];
+        for (bit_buffer, data, empty) in streams.iter() {
+            assert_eq!(
+                decompress_with_bit_buffer(data, 1024, *bit_buffer).unwrap(),
+                [0xa5; 1024]
+            );
+            assert_eq!(
+                decompress_with_bit_buffer(empty, 1024, *bit_buffer).unwrap(),
+                b""
+            );
+            // streams are never interchangeable between packings
+            for (other, _, _) in streams.iter().filter(|(other, _, _)| other != bit_buffer) {
+                assert!(decompress_with_bit_buffer(data, 1024, *other).is_err());
+                assert!(decompress_with_bit_buffer(empty, 1024, *other).is_err());
+            }
+        }
+
+        let mut trailing = streams[2].1.to_vec();
+        trailing.push(0);
+        assert_eq!(
+            decompress_with_bit_buffer(&trailing, 1024, BitBuffer::Le32),
+            Err(UclErrorKind::InputNotConsumed)
+        );
+    }
+
+    #[test]
+    fn decompress_empty_src() {
+        setup();
+        assert_eq!(
+            decompress(b"", 1024).unwrap_err(),
+            UclErrorKind::InvalidArgument
+        );
+        assert_eq!(
+            decompress_into_buffer(b"", &mut [0; 16]).unwrap_err(),
+            UclErrorKind::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn decompress_zero_capacity() {
+        setup();
+        assert_eq!(
+            decompress(b"\x00\x00\x00\x00\x00\x04\x80\xff", 0).unwrap(),
+            b""
+        );
+        assert_eq!(
+            decompress(b"\x92\xa5\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff", 0).unwrap_err(),
+            UclErrorKind::OutputOverrun
+        );
+        assert_eq!(
+            decompress_into_buffer(b"\x92\xa5\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff", &mut [])
+                .unwrap_err(),
             UclErrorKind::OutputOverrun
         );
     }
 
     #[test]
-    fn decompress_src_too_big() {
-        ucl_init();
-        let input = vec![0u8; 4 * 1024 * 1024 * 1024];
+    fn decompress_all_records() {
+        setup();
+        let records: Vec<Vec<u8>> = (0..5u8).map(|i| vec![i; 100 * usize::from(i)]).collect();
+        let mut src = Vec::new();
+        let mut offsets = Vec::new();
+        for record in records.iter() {
+            offsets.push(src.len());
+            src.extend_from_slice(&compress(record).unwrap());
+        }
+        assert_eq!(try_decompress_all(&src, 400).unwrap(), records);
+        assert_eq!(try_decompress_all(&[], 400).unwrap(), Vec::<Vec<u8>>::new());
+
+        let err = try_decompress_all(&src, 300).unwrap_err();
         assert_eq!(
-            decompress(&input, 4).unwrap_err(),
-            UclErrorKind::SrcTooLarge
+            err,
+            DecompressAllError {
+                decoded: 4,
+                offset: offsets[4],
+                kind: UclErrorKind::OutputOverrun,
+            }
         );
+
+        let err = try_decompress_all(&src[..src.len() - 1], 400).unwrap_err();
+        assert_eq!((err.decoded, err.offset), (4, offsets[4]));
     }
 
     #[test]
-    fn decompress_nothing() {
-        ucl_init();
-        let compressed = b"\x00\x00\x00\x00\x00\x04\x80\xff";
-        assert_eq!(decompress(compressed.as_ref(), 8).unwrap(), b"");
+    fn compress_stats_nothing() {
+        setup();
+        let (dst, stats) = compress_with_stats(&[]).unwrap();
+        assert_eq!(dst, b"\x00\x00\x00\x00\x00\x04\x80\xff");
+        assert_eq!(stats.input_len, 0);
+        assert_eq!(stats.output_len, 8);
+        assert!(stats.ratio.is_infinite());
     }
 
     #[test]
-    fn decompress_8k_too_small() {
-        ucl_init();
+    fn init_concurrently() {
+        let threads: Vec<_> = (0..8).map(|_| std::thread::spawn(ucl_init)).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn compress_concurrently_without_init() {
+        let threads: Vec<_> = (0..8)
+            .map(|_| std::thread::spawn(|| compress(&[0; 1024]).unwrap()))
+            .collect();
+        for t in threads {
+            assert_eq!(
+                t.join().unwrap(),
+                b"\x92\x00\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff"
+            );
+        }
+    }
+
+    #[test]
+    fn decompress_auto_8k() {
         let compressed =
             b"\x92\x00\xaa\xa8\xc9\x55\x54\x64\xaa\xaa\x32\x55\x55\x08\x00\x00\x00\x00\x00\x24\xff";
         assert_eq!(
-            decompress(compressed.as_ref(), 8191).unwrap_err(),
+            decompress_auto(compressed.as_ref(), 0).unwrap(),
+            [0u8; 8192].as_ref()
+        );
+    }
+
+    #[test]
+    fn checksums_nothing() {
+        assert_eq!(adler32(1, &[]), 1);
+        assert_eq!(crc32(0, &[]), 0);
+    }
+
+    #[test]
+    fn error_into_io_error() {
+        let err = std::io::Error::from(UclErrorKind::EofNotFound);
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "EOF not found");
+
+        let err = std::io::Error::from(UclErrorKind::DstTooSmall { needed: 2, got: 1 });
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(
+            err.into_inner()
+                .unwrap()
+                .downcast::<UclErrorKind>()
+                .unwrap(),
+            Box::new(UclErrorKind::DstTooSmall { needed: 2, got: 1 })
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_roundtrip() {
+        for kind in [
+            UclErrorKind::OutputOverrun,
+            UclErrorKind::Unknown(-42),
+            UclErrorKind::DstTooSmall { needed: 2, got: 1 },
+            UclErrorKind::Io(std::io::ErrorKind::UnexpectedEof),
+        ] {
+            let json = serde_json::to_string(&kind).unwrap();
+            assert_eq!(serde_json::from_str::<UclErrorKind>(&json).unwrap(), kind);
+        }
+        assert_eq!(
+            serde_json::to_string(&UclErrorKind::Io(std::io::ErrorKind::InvalidData)).unwrap(),
+            r#"{"Io":"InvalidData"}"#
+        );
+
+        let stats = CompressStats::new(1024, 12);
+        let json = serde_json::to_string(&stats).unwrap();
+        assert_eq!(serde_json::from_str::<CompressStats>(&json).unwrap(), stats);
+    }
+
+    #[test]
+    fn error_codes() {
+        assert_eq!(UclErrorKind::from(-1), UclErrorKind::GenericError);
+        assert_eq!(UclErrorKind::from(-206), UclErrorKind::OverlapOverrun);
+        assert_eq!(UclErrorKind::from(-42), UclErrorKind::Unknown(-42));
+        assert_eq!(
+            UclErrorKind::Unknown(-42).to_string(),
+            "unknown UCL error -42"
+        );
+    }
+
+    #[test]
+    fn compress_levels() {
+        let src: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+        for level in MIN_LEVEL..=MAX_LEVEL {
+            let compressed = compress_with_level(&src, level).unwrap();
+            assert_eq!(decompress(&compressed, 10000).unwrap(), src);
+        }
+        assert_eq!(
+            compress_with_level(&src, 0).unwrap_err(),
+            UclErrorKind::InvalidArgument
+        );
+        assert_eq!(
+            compress_with_level(&src, MAX_LEVEL + 1).unwrap_err(),
+            UclErrorKind::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn compress_progress_panic() {
+        let result = std::panic::catch_unwind(|| {
+            compress_with_progress(&[0; 100000], 6, |_, _| panic!("abort"))
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn decompress_in_place_roundtrip() {
+        let src: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+        let compressed = compress(&src).unwrap();
+        let overhead = in_place_decompression_overhead(src.len());
+
+        let mut buf = vec![0u8; src.len() + overhead];
+        buf[..compressed.len()].copy_from_slice(&compressed);
+        assert_eq!(
+            decompress_in_place(&mut buf, compressed.len(), overhead).unwrap(),
+            10000
+        );
+        assert_eq!(&buf[..10000], src.as_slice());
+    }
+
+    #[test]
+    fn decompress_in_place_no_overhead() {
+        let src: Vec<u8> = (0..10000u32).map(|i| (i * 7919 % 256) as u8).collect();
+        let compressed = compress(&src).unwrap();
+
+        let mut buf = vec![0u8; src.len()];
+        buf[..compressed.len()].copy_from_slice(&compressed);
+        assert_eq!(
+            decompress_in_place(&mut buf, compressed.len(), 0).unwrap_err(),
+            UclErrorKind::OverlapOverrun
+        );
+        assert_eq!(&buf[buf.len() - compressed.len()..], compressed.as_slice());
+    }
+
+    #[test]
+    fn decompress_in_place_invalid() {
+        let mut buf = [0u8; 16];
+        assert_eq!(
+            decompress_in_place(&mut buf, 17, 0).unwrap_err(),
+            UclErrorKind::InvalidArgument
+        );
+        assert_eq!(
+            decompress_in_place(&mut buf, 8, 17).unwrap_err(),
+            UclErrorKind::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn decompress_in_place_empty() {
+        let mut buf = [0u8; 16];
+        assert_eq!(
+            decompress_in_place(&mut buf, 0, 0).unwrap_err(),
+            UclErrorKind::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn decompress_in_place_respects_capacity() {
+        let compressed = b"\x92\xa5\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff";
+        let overhead = in_place_decompression_overhead(1024);
+
+        let mut buf = vec![0u8; 1024 + overhead];
+        buf[..compressed.len()].copy_from_slice(compressed);
+        assert_eq!(
+            decompress_in_place(&mut buf, compressed.len(), overhead + 1).unwrap_err(),
             UclErrorKind::OutputOverrun
         );
     }
+
+    #[test]
+    fn compressor_reuse() {
+        let mut compressor = Compressor::with_level(MAX_LEVEL);
+        let big: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+        let compressed = compressor.compress(&big).unwrap();
+        assert_eq!(decompress(&compressed, 10000).unwrap(), big);
+
+        assert_eq!(
+            compressor.compress_into(&[]).unwrap(),
+            b"\x00\x00\x00\x00\x00\x04\x80\xff"
+        );
+
+        let compressed = compressor.compress(&big[..5000]).unwrap();
+        assert_eq!(decompress(&compressed, 5000).unwrap(), &big[..5000]);
+    }
+
+    #[test]
+    fn compressor_invalid_level() {
+        assert_eq!(
+            Compressor::with_level(0).compress(b"abc").unwrap_err(),
+            UclErrorKind::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn maybe_compress_never_expands() {
+        let src: Vec<u8> = (0..=255u8).collect();
+        assert_eq!(maybe_compress(&src).unwrap(), (false, src));
+        assert_eq!(maybe_compress(&[]).unwrap(), (false, Vec::new()));
+
+        let (compressed, data) = maybe_compress(&[0; 4096]).unwrap();
+        assert!(compressed);
+        assert!(data.len() < 4096);
+    }
+
+    #[test]
+    fn compress_best_is_smallest() {
+        let src: Vec<u8> = (0..10000u32).map(|i| (i * i % 251) as u8).collect();
+        let (best, level) = compress_best(&src).unwrap();
+        assert_eq!(compress_with_level(&src, level).unwrap(), best);
+        for l in MIN_LEVEL..level {
+            assert!(compress_with_level(&src, l).unwrap().len() > best.len());
+        }
+        for l in level..=MAX_LEVEL {
+            assert!(compress_with_level(&src, l).unwrap().len() >= best.len());
+        }
+    }
+
+    #[test]
+    fn compress_best_nothing() {
+        assert_eq!(
+            compress_best(&[]).unwrap(),
+            (b"\x00\x00\x00\x00\x00\x04\x80\xff".to_vec(), MIN_LEVEL)
+        );
+    }
+
+    #[test]
+    fn lazy_init_after_reset() {
+        reset_for_test();
+        assert_eq!(
+            compress(&[0; 1024]).unwrap(),
+            b"\x92\x00\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff"
+        );
+        assert!(INITIALIZED.load(Ordering::Acquire));
+
+        reset_for_test();
+        let mut buf = [0u8; 1024];
+        assert_eq!(
+            decompress_into_buffer_unchecked(
+                b"\x92\x00\xaa\xa1\x00\x00\x00\x00\x00\x04\x80\xff",
+                &mut buf
+            ),
+            Ok(1024)
+        );
+        assert!(INITIALIZED.load(Ordering::Acquire));
+
+        reset_for_test();
+        assert_eq!(ucl_init_checked(), Ok(()));
+        assert!(INITIALIZED.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn init_checked() {
+        assert_eq!(ucl_init_checked(), Ok(()));
+        assert_eq!(ucl_init_checked(), Ok(()));
+    }
+
+    #[test]
+    fn linked_version_is_compatible() {
+        assert!(
+            is_compatible_version(library_version()),
+            "linked libucl {} ({:#08x}) is incompatible with bindings for {:#08x}",
+            library_version_string(),
+            library_version(),
+            UCL_VERSION
+        );
+        assert!(library_version_string().starts_with(&format!("{}.", UCL_VERSION >> 16)));
+    }
+
+    #[test]
+    fn decompress_with_capacity_too_big() {
+        assert_eq!(
+            decompress_with_capacity(b"\x00\x00\x00\x00\x00\x04\x80\xff", u32::MAX as usize + 1)
+                .unwrap_err(),
+            UclErrorKind::DstTooLarge
+        );
+    }
+
+    #[test]
+    fn slice_roundtrip() {
+        let src: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+        let mut compressed = vec![0u8; minimum_compression_buffer_size(src.len())];
+        let nb = compress_into_slice(&src, &mut compressed).unwrap();
+
+        let mut decompressed = vec![0u8; src.len()];
+        assert_eq!(
+            decompress_into_slice(&compressed[..nb], &mut decompressed).unwrap(),
+            src.len()
+        );
+        assert_eq!(decompressed, src);
+    }
+
+    #[test]
+    fn decompress_to_writer_stream() {
+        let src: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+        let mut compressed = Vec::new();
+        crate::frame::compress_stream(&mut &src[..], &mut compressed, 6).unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(
+            decompress_to_writer(&compressed, 0, &mut out).unwrap(),
+            10000
+        );
+        assert_eq!(out, src);
+
+        compressed.truncate(compressed.len() - 1);
+        assert_eq!(
+            decompress_to_writer(&compressed, 0, &mut Vec::new()).unwrap_err(),
+            UclErrorKind::Io(std::io::ErrorKind::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn decompress_to_writer_full() {
+        let mut out = [0u8; 4];
+        let compressed = compress(b"hello").unwrap();
+        assert_eq!(
+            decompress_to_writer(&compressed, 5, &mut &mut out[..]).unwrap_err(),
+            UclErrorKind::Io(std::io::ErrorKind::WriteZero)
+        );
+    }
+
+    #[test]
+    fn io_error_into_error() {
+        let err = std::io::Error::from(UclErrorKind::EofNotFound);
+        let kind: UclErrorKind = err.into();
+        assert_eq!(kind, UclErrorKind::EofNotFound);
+        let err = std::io::Error::from(std::io::ErrorKind::BrokenPipe);
+        let kind: UclErrorKind = err.into();
+        assert_eq!(kind, UclErrorKind::Io(std::io::ErrorKind::BrokenPipe));
+    }
+
+    #[test]
+    fn compress_from_reader_invalid_level() {
+        assert_eq!(
+            compress_from_reader(&mut std::io::empty(), &mut std::io::sink(), 11).unwrap_err(),
+            UclErrorKind::InvalidArgument
+        );
+    }
+
+    fn roundtrip(src: &[u8]) {
+        let compressed = compress(src).unwrap();
+        let decompressed = decompress(&compressed, src.len() as u32).unwrap();
+        assert_eq!(decompressed, src);
+    }
+
+    #[test]
+    fn roundtrip_edge_cases() {
+        roundtrip(&[]);
+        roundtrip(&[0x42]);
+        roundtrip(&[0xa5; 100000]);
+        roundtrip(&b"abc".repeat(10000));
+    }
+
+    proptest! {
+        #[test]
+        fn roundtrip_random(src in proptest::collection::vec(any::<u8>(), 0..65536)) {
+            let compressed = compress(&src).unwrap();
+            let decompressed = decompress(&compressed, src.len() as u32).unwrap();
+            prop_assert_eq!(decompressed, src);
+        }
+
+        #[test]
+        fn roundtrip_repetitive(
+            chunk in proptest::collection::vec(any::<u8>(), 1..16),
+            count in 0usize..4096,
+        ) {
+            let src = chunk.repeat(count);
+            let compressed = compress(&src).unwrap();
+            let decompressed = decompress(&compressed, src.len() as u32).unwrap();
+            prop_assert_eq!(decompressed, src);
+        }
+    }
 }
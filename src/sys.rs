@@ -0,0 +1,171 @@
+/*
+ * uclcli sys.rs - raw FFI declarations for libucl
+ * Copyright (C) 2020-2021  BMW Group
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Raw FFI declarations for libucl.
+//!
+//! With the `bindgen` feature, these are generated from the installed `ucl/ucl.h` at build
+//! time, so they always match the headers of the library being linked. Without it, the
+//! hand-written declarations below are used, which mirror what bindgen generates for
+//! libucl 1.03.
+
+#![allow(non_camel_case_types, non_upper_case_globals, dead_code)]
+
+#[cfg(feature = "bindgen")]
+include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+
+/// `ucl_voidp` is a macro in libucl's `uclconf.h`, so bindgen does not generate it
+pub type ucl_voidp = *mut libc::c_void;
+
+#[cfg(not(feature = "bindgen"))]
+pub use self::fallback::*;
+
+#[cfg(not(feature = "bindgen"))]
+mod fallback {
+    use super::ucl_voidp;
+    use libc::{c_char, c_int, c_uint};
+
+    pub const UCL_VERSION: u32 = 0x01_0300;
+
+    pub type ucl_uint = c_uint;
+    pub type ucl_uint32 = c_uint;
+
+    #[repr(C)]
+    #[derive(Debug, Copy, Clone)]
+    pub struct ucl_progress_callback_t {
+        pub callback: Option<unsafe extern "C" fn(ucl_uint, ucl_uint, c_int, ucl_voidp)>,
+        pub user: ucl_voidp,
+    }
+
+    #[repr(C)]
+    #[derive(Debug, Copy, Clone)]
+    pub struct ucl_compress_config_t {
+        pub bb_endian: c_int,
+        pub bb_size: c_int,
+        pub max_offset: ucl_uint,
+        pub max_match: ucl_uint,
+        pub s_level: c_int,
+        pub h_level: c_int,
+        pub p_level: c_int,
+        pub c_flags: c_int,
+        pub m_size: ucl_uint,
+    }
+
+    extern "C" {
+        pub fn __ucl_init2(
+            v: ucl_uint32,
+            s1: c_int,
+            s2: c_int,
+            s3: c_int,
+            s4: c_int,
+            s5: c_int,
+            s6: c_int,
+            s7: c_int,
+            s8: c_int,
+            s9: c_int,
+        ) -> c_int;
+
+        pub fn ucl_version() -> ucl_uint32;
+
+        pub fn ucl_version_string() -> *const c_char;
+
+        pub fn ucl_nrv2b_decompress_safe_8(
+            src: *const u8,
+            src_len: ucl_uint,
+            dst: *mut u8,
+            dst_len: *mut ucl_uint,
+            wrkmem: ucl_voidp,
+        ) -> c_int;
+
+        pub fn ucl_nrv2b_decompress_safe_le16(
+            src: *const u8,
+            src_len: ucl_uint,
+            dst: *mut u8,
+            dst_len: *mut ucl_uint,
+            wrkmem: ucl_voidp,
+        ) -> c_int;
+
+        pub fn ucl_nrv2b_decompress_safe_le32(
+            src: *const u8,
+            src_len: ucl_uint,
+            dst: *mut u8,
+            dst_len: *mut ucl_uint,
+            wrkmem: ucl_voidp,
+        ) -> c_int;
+
+        pub fn ucl_nrv2d_decompress_safe_8(
+            src: *const u8,
+            src_len: ucl_uint,
+            dst: *mut u8,
+            dst_len: *mut ucl_uint,
+            wrkmem: ucl_voidp,
+        ) -> c_int;
+
+        pub fn ucl_nrv2e_decompress_safe_8(
+            src: *const u8,
+            src_len: ucl_uint,
+            dst: *mut u8,
+            dst_len: *mut ucl_uint,
+            wrkmem: ucl_voidp,
+        ) -> c_int;
+
+        pub fn ucl_nrv2b_test_overlap_8(
+            src: *const u8,
+            src_off: ucl_uint,
+            src_len: ucl_uint,
+            dst_len: *mut ucl_uint,
+            wrkmem: ucl_voidp,
+        ) -> c_int;
+
+        pub fn ucl_nrv2b_99_compress(
+            src: *const u8,
+            src_len: ucl_uint,
+            dst: *mut u8,
+            dst_len: *mut ucl_uint,
+            cb: *mut ucl_progress_callback_t,
+            level: c_int,
+            conf: *const ucl_compress_config_t,
+            result: *mut ucl_uint,
+        ) -> c_int;
+
+        pub fn ucl_nrv2d_99_compress(
+            src: *const u8,
+            src_len: ucl_uint,
+            dst: *mut u8,
+            dst_len: *mut ucl_uint,
+            cb: *mut ucl_progress_callback_t,
+            level: c_int,
+            conf: *const ucl_compress_config_t,
+            result: *mut ucl_uint,
+        ) -> c_int;
+
+        pub fn ucl_nrv2e_99_compress(
+            src: *const u8,
+            src_len: ucl_uint,
+            dst: *mut u8,
+            dst_len: *mut ucl_uint,
+            cb: *mut ucl_progress_callback_t,
+            level: c_int,
+            conf: *const ucl_compress_config_t,
+            result: *mut ucl_uint,
+        ) -> c_int;
+
+        pub fn ucl_adler32(c: ucl_uint32, buf: *const u8, len: ucl_uint) -> ucl_uint32;
+
+        pub fn ucl_crc32(c: ucl_uint32, buf: *const u8, len: ucl_uint) -> ucl_uint32;
+    }
+}
@@ -0,0 +1,610 @@
+/*
+ * uclcli tokio.rs - async wrappers for tokio
+ * Copyright (C) 2020-2021  BMW Group
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Async compression and decompression for tokio.
+//!
+//! libucl blocks the calling thread for as long as it takes to compress a buffer, which would
+//! stall every other task on an async runtime's worker thread. The functions and adapters here
+//! run it on tokio's blocking thread pool instead, see [spawn_blocking]. As the work moves to
+//! another thread, they take ownership of their input.
+//!
+//! ```
+//! let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+//! rt.block_on(async {
+//!     let compressed = uclcli::tokio::compress_async(vec![0; 1024]).await.unwrap();
+//!     let decompressed = uclcli::tokio::decompress_async(compressed, 1024).await.unwrap();
+//!     assert_eq!(decompressed, [0u8; 1024].as_ref());
+//! });
+//! ```
+
+use std::future::Future;
+use std::io;
+use std::mem;
+use std::panic;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use ::tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use ::tokio::task::{spawn_blocking, JoinError, JoinHandle};
+
+use crate::io::{
+    decompress_block, parse_block_header, write_block, BLOCK_HEADER_LEN, DEFAULT_BLOCK_SIZE,
+    MAX_BLOCK_SIZE,
+};
+use crate::{
+    compress, compress_into_buffer_with_level, decompress, minimum_compression_buffer_size,
+    UclErrorKind, DEFAULT_LEVEL,
+};
+
+/// the result of a blocking task, passing on its panic if it had one
+///
+/// A task which never ran because the runtime shut down is reported as
+/// `Err(UclErrorKind::Io(io::ErrorKind::Interrupted))`.
+fn join<T, E: From<UclErrorKind>>(
+    result: Result<core::result::Result<T, E>, JoinError>,
+) -> core::result::Result<T, E> {
+    match result {
+        Ok(result) => result,
+        Err(err) if err.is_panic() => panic::resume_unwind(err.into_panic()),
+        Err(_) => Err(UclErrorKind::Io(io::ErrorKind::Interrupted).into()),
+    }
+}
+
+/// NRV compress a buffer on tokio's blocking thread pool, see [crate::compress].
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [crate::ucl_init]. It must be
+/// called from within a tokio runtime.
+pub async fn compress_async(src: Vec<u8>) -> core::result::Result<Vec<u8>, UclErrorKind> {
+    join(spawn_blocking(move || compress(&src)).await)
+}
+
+/// decompress a NRV compressed buffer on tokio's blocking thread pool, see
+/// [crate::decompress].
+///
+/// # Panics
+/// If libucl cannot be initialized, this function will panic, see [crate::ucl_init]. It must be
+/// called from within a tokio runtime.
+pub async fn decompress_async(
+    src: Vec<u8>,
+    dst_capacity: u32,
+) -> core::result::Result<Vec<u8>, UclErrorKind> {
+    join(spawn_blocking(move || decompress(&src, dst_capacity)).await)
+}
+
+/// An [AsyncWrite] adapter that compresses everything written to it into a block stream.
+///
+/// This writes the same format as [UclWriter](crate::io::UclWriter), compressing every block on
+/// tokio's blocking thread pool. While a block is compressed and written, the next one is not
+/// accepted yet, so at most two blocks are held in memory. Calling
+/// [flush](::tokio::io::AsyncWriteExt::flush) forces the current (partial) block out. Unlike
+/// [UclWriter](crate::io::UclWriter), this cannot write the last block and the end of the
+/// stream on drop, so [shutdown](::tokio::io::AsyncWriteExt::shutdown) must be called to
+/// finish the stream.
+///
+/// ```
+/// use tokio::io::AsyncWriteExt;
+///
+/// let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+/// rt.block_on(async {
+///     let mut writer = uclcli::tokio::AsyncUclWriter::new(Vec::new());
+///     writer.write_all(&[0; 1024]).await.unwrap();
+///     writer.shutdown().await.unwrap();
+///     assert_eq!(&writer.get_ref()[..8], b"\x00\x04\x00\x00\x0c\x00\x00\x00");
+/// });
+/// ```
+pub struct AsyncUclWriter<W: AsyncWrite + Unpin> {
+    inner: W,
+    block_size: usize,
+    level: u8,
+    buf: Vec<u8>,
+    state: WriteState,
+    /// whether the end of the stream has been queued for writing
+    ended: bool,
+}
+
+enum WriteState {
+    /// collecting data for the next block in `buf`
+    Buffering,
+    /// compressing a block into its encoded form, including the header
+    Compressing(JoinHandle<io::Result<Vec<u8>>>),
+    /// writing an encoded block to the inner writer
+    Writing { out: Vec<u8>, pos: usize },
+    /// compressing a block failed with an error of the given kind, which is reported again on
+    /// every write, as the block is lost
+    Failed(io::ErrorKind),
+}
+
+/// compress `block` and encode it with its block header
+fn encode_block(block: &[u8], level: u8) -> io::Result<Vec<u8>> {
+    let mut compressed = vec![0; minimum_compression_buffer_size(block.len())];
+    let nb = compress_into_buffer_with_level(block, &mut compressed, level)? as usize;
+
+    let mut out = Vec::with_capacity(BLOCK_HEADER_LEN + nb);
+    write_block(&mut out, block.len(), &compressed[..nb])?;
+    Ok(out)
+}
+
+impl<W: AsyncWrite + Unpin> AsyncUclWriter<W> {
+    /// create a new writer with [DEFAULT_BLOCK_SIZE]
+    pub fn new(inner: W) -> Self {
+        Self::with_block_size(inner, DEFAULT_BLOCK_SIZE)
+    }
+
+    /// create a new writer compressing `block_size` bytes at a time
+    ///
+    /// # Panics
+    /// If `block_size` is zero or larger than [MAX_BLOCK_SIZE], this function will panic.
+    pub fn with_block_size(inner: W, block_size: usize) -> Self {
+        assert!(
+            block_size > 0 && block_size <= MAX_BLOCK_SIZE,
+            "invalid block size"
+        );
+        AsyncUclWriter {
+            inner,
+            block_size,
+            level: DEFAULT_LEVEL,
+            buf: Vec::new(),
+            state: WriteState::Buffering,
+            ended: false,
+        }
+    }
+
+    /// set the compression level used for subsequent blocks
+    ///
+    /// An invalid level results in an error of kind
+    /// [InvalidInput](io::ErrorKind::InvalidInput) once the next block is written.
+    pub fn set_level(&mut self, level: u8) {
+        self.level = level;
+    }
+
+    /// get a reference to the inner writer
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// return the inner writer, discarding any data not written yet
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// start compressing the buffered data, which must not be empty
+    ///
+    /// Must only be called in [WriteState::Buffering].
+    fn start_block(&mut self) {
+        let block = mem::take(&mut self.buf);
+        let level = self.level;
+        self.state = WriteState::Compressing(spawn_blocking(move || encode_block(&block, level)));
+    }
+
+    /// drive a pending block until it is completely written to the inner writer
+    fn poll_block(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            match &mut self.state {
+                WriteState::Buffering => return Poll::Ready(Ok(())),
+                WriteState::Compressing(handle) => match join(ready!(Pin::new(handle).poll(cx))) {
+                    Ok(out) => self.state = WriteState::Writing { out, pos: 0 },
+                    Err(err) => {
+                        self.state = WriteState::Failed(err.kind());
+                        return Poll::Ready(Err(err));
+                    }
+                },
+                WriteState::Writing { out, pos } => {
+                    while *pos < out.len() {
+                        let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &out[*pos..]))?;
+                        if n == 0 {
+                            return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                        }
+                        *pos += n;
+                    }
+                    self.state = WriteState::Buffering;
+                }
+                WriteState::Failed(kind) => return Poll::Ready(Err((*kind).into())),
+            }
+        }
+    }
+
+    /// write out all buffered data, including a partial block
+    fn poll_blocks(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            ready!(self.poll_block(cx))?;
+            if self.buf.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            self.start_block();
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncUclWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_block(cx))?;
+
+        let n = data.len().min(this.block_size - this.buf.len());
+        this.buf.extend_from_slice(&data[..n]);
+        if this.buf.len() == this.block_size {
+            this.start_block();
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_blocks(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_blocks(cx))?;
+        if !this.ended {
+            this.ended = true;
+            this.state = WriteState::Writing {
+                out: vec![0; BLOCK_HEADER_LEN],
+                pos: 0,
+            };
+            ready!(this.poll_block(cx))?;
+        }
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// An [AsyncRead] adapter that decompresses a block stream as written by [AsyncUclWriter] or
+/// [UclWriter](crate::io::UclWriter).
+///
+/// Blocks are read one at a time and decompressed on tokio's blocking thread pool, so memory
+/// usage is bounded by the block size. Errors are reported like
+/// [UclReader](crate::io::UclReader) does.
+///
+/// ```
+/// use std::io::Write;
+/// use tokio::io::AsyncReadExt;
+///
+/// let mut writer = uclcli::io::UclWriter::new(Vec::new());
+/// writer.write_all(b"hello world").unwrap();
+/// let compressed = writer.finish().unwrap();
+///
+/// let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+/// rt.block_on(async {
+///     let mut decompressed = String::new();
+///     let mut reader = uclcli::tokio::AsyncUclReader::new(&compressed[..]);
+///     reader.read_to_string(&mut decompressed).await.unwrap();
+///     assert_eq!(decompressed, "hello world");
+/// });
+/// ```
+pub struct AsyncUclReader<R: AsyncRead + Unpin> {
+    inner: R,
+    state: ReadState,
+}
+
+enum ReadState {
+    /// reading the header of the next block
+    Header {
+        header: [u8; BLOCK_HEADER_LEN],
+        filled: usize,
+    },
+    /// reading the `clen` bytes of compressed data of a block
+    Body {
+        ulen: usize,
+        clen: usize,
+        compressed: Vec<u8>,
+    },
+    /// decompressing a block
+    Decompressing(JoinHandle<io::Result<Vec<u8>>>),
+    /// handing out the decompressed data of a block
+    Output { buf: Vec<u8>, pos: usize },
+    /// the stream ended cleanly at an empty block header
+    Done,
+    /// reading failed with an error of the given kind, which is reported again on every read
+    Failed(io::ErrorKind),
+}
+
+impl ReadState {
+    fn header() -> Self {
+        ReadState::Header {
+            header: [0; BLOCK_HEADER_LEN],
+            filled: 0,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncUclReader<R> {
+    /// create a new reader decompressing the block stream read from `inner`
+    pub fn new(inner: R) -> Self {
+        AsyncUclReader {
+            inner,
+            state: ReadState::header(),
+        }
+    }
+
+    /// get a reference to the inner reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// return the inner reader, discarding any buffered data
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+/// read from `inner` into `buf`, returning the number of bytes read
+fn poll_read_into<R: AsyncRead + Unpin>(
+    inner: &mut R,
+    cx: &mut Context<'_>,
+    buf: &mut [u8],
+) -> Poll<io::Result<usize>> {
+    let mut buf = ReadBuf::new(buf);
+    ready!(Pin::new(inner).poll_read(cx, &mut buf))?;
+    Poll::Ready(Ok(buf.filled().len()))
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncUclReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        out: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if out.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        let result = this.poll_output(cx, out);
+        if let Poll::Ready(Err(err)) = &result {
+            // later blocks cannot be trusted to follow on from where the error occurred
+            this.state = ReadState::Failed(err.kind());
+        }
+        result
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncUclReader<R> {
+    /// advance through the stream until some decompressed data is copied to `out`
+    fn poll_output(&mut self, cx: &mut Context<'_>, out: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let AsyncUclReader { inner, state } = self;
+        loop {
+            match state {
+                ReadState::Header { header, filled } => {
+                    let n = ready!(poll_read_into(inner, cx, &mut header[*filled..]))?;
+                    if n == 0 {
+                        // the stream must end with an empty block header
+                        return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                    }
+                    *filled += n;
+                    if *filled == BLOCK_HEADER_LEN {
+                        let (ulen, clen) = parse_block_header(header)?;
+                        *state = if (ulen, clen) == (0, 0) {
+                            ReadState::Done
+                        } else {
+                            ReadState::Body {
+                                ulen,
+                                clen,
+                                compressed: Vec::new(),
+                            }
+                        };
+                    }
+                }
+                ReadState::Body {
+                    ulen,
+                    clen,
+                    compressed,
+                } => {
+                    let filled = compressed.len();
+                    if filled < *clen {
+                        // grow with the data actually read, so a forged header alone cannot
+                        // cause a large allocation
+                        let grow = (*clen - filled).min(filled.max(DEFAULT_BLOCK_SIZE));
+                        compressed.resize(filled + grow, 0);
+                        let result = poll_read_into(inner, cx, &mut compressed[filled..]);
+                        let n = match result {
+                            Poll::Ready(Ok(n)) => n,
+                            _ => 0,
+                        };
+                        compressed.truncate(filled + n);
+                        if ready!(result)? == 0 {
+                            return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                        }
+                        continue;
+                    }
+
+                    let ulen = *ulen;
+                    let compressed = mem::take(compressed);
+                    *state = ReadState::Decompressing(spawn_blocking(move || {
+                        let mut buf = Vec::new();
+                        decompress_block(&compressed, ulen, &mut buf)?;
+                        Ok(buf)
+                    }));
+                }
+                ReadState::Decompressing(handle) => {
+                    // on error, the state becomes `Failed`, so the finished task is never
+                    // polled again
+                    let buf = join(ready!(Pin::new(handle).poll(cx)))?;
+                    *state = ReadState::Output { buf, pos: 0 };
+                }
+                ReadState::Output { buf, pos } => {
+                    if *pos == buf.len() {
+                        *state = ReadState::header();
+                        continue;
+                    }
+                    let n = out.remaining().min(buf.len() - *pos);
+                    out.put_slice(&buf[*pos..*pos + n]);
+                    *pos += n;
+                    return Poll::Ready(Ok(()));
+                }
+                ReadState::Done => return Poll::Ready(Ok(())),
+                ReadState::Failed(kind) => return Poll::Ready(Err((*kind).into())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+
+    use ::tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use ::tokio::runtime::Builder;
+
+    use super::{compress_async, decompress_async, AsyncUclReader, AsyncUclWriter};
+    use crate::io::{UclWriter, BLOCK_HEADER_LEN};
+    use crate::{compress, UclErrorKind};
+
+    #[test]
+    fn async_roundtrip() {
+        let rt = Builder::new_current_thread().build().unwrap();
+        rt.block_on(async {
+            let src: Vec<u8> = (0..10_000u32).map(|i| (i % 97) as u8).collect();
+            let compressed = compress_async(src.clone()).await.unwrap();
+            assert_eq!(compressed, compress(&src).unwrap());
+
+            assert_eq!(
+                decompress_async(compressed.clone(), 10_000).await.unwrap(),
+                src
+            );
+            assert_eq!(
+                decompress_async(compressed, 9_999).await,
+                Err(UclErrorKind::OutputOverrun)
+            );
+        });
+    }
+
+    #[test]
+    fn async_stream_matches_sync_stream() {
+        let src: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let mut sync = UclWriter::with_block_size(Vec::new(), 1000);
+        sync.write_all(&src).unwrap();
+        let expected = sync.finish().unwrap();
+
+        let rt = Builder::new_current_thread().build().unwrap();
+        rt.block_on(async {
+            let mut writer = AsyncUclWriter::with_block_size(Vec::new(), 1000);
+            // odd write sizes, so writes straddle block boundaries
+            for chunk in src.chunks(333) {
+                writer.write_all(chunk).await.unwrap();
+            }
+            writer.shutdown().await.unwrap();
+            let compressed = writer.into_inner();
+            assert_eq!(compressed, expected);
+
+            let mut reader = AsyncUclReader::new(&compressed[..]);
+            let mut decompressed = Vec::new();
+            let mut buf = [0u8; 7];
+            loop {
+                let n = reader.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                decompressed.extend_from_slice(&buf[..n]);
+            }
+            assert_eq!(decompressed, src);
+        });
+    }
+
+    #[test]
+    fn async_reader_stops_at_end_marker() {
+        let mut sync = UclWriter::with_block_size(Vec::new(), 4);
+        sync.write_all(b"0123456789").unwrap();
+        let mut stream = sync.finish().unwrap();
+        stream.extend_from_slice(b"trailer");
+
+        let rt = Builder::new_current_thread().build().unwrap();
+        rt.block_on(async {
+            let mut reader = AsyncUclReader::new(&stream[..]);
+            let mut out = Vec::new();
+            reader.read_to_end(&mut out).await.unwrap();
+            assert_eq!(out, b"0123456789");
+            assert_eq!(reader.read(&mut [0; 4]).await.unwrap(), 0);
+            assert_eq!(reader.into_inner(), b"trailer");
+        });
+    }
+
+    #[test]
+    fn async_writer_keeps_failing() {
+        let rt = Builder::new_current_thread().build().unwrap();
+        rt.block_on(async {
+            let mut writer = AsyncUclWriter::with_block_size(Vec::new(), 4);
+            writer.set_level(0);
+            writer.write_all(b"0123").await.unwrap();
+            let err = writer.flush().await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            let err = writer.write_all(b"4567").await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert!(writer.into_inner().is_empty());
+        });
+    }
+
+    #[test]
+    fn async_reader_stops_at_corrupt_block() {
+        let mut sync = UclWriter::with_block_size(Vec::new(), 4);
+        sync.write_all(b"0123456789").unwrap();
+        let mut stream = sync.finish().unwrap();
+        // claim one byte too many for the first block
+        stream[0] = 5;
+
+        let rt = Builder::new_current_thread().build().unwrap();
+        rt.block_on(async {
+            let mut reader = AsyncUclReader::new(&stream[..]);
+            let mut buf = [0; 16];
+            let err = reader.read(&mut buf).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            let err = reader.read(&mut buf).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        });
+    }
+
+    #[test]
+    fn async_reader_forged_block_length() {
+        let mut header = 1_000_000u32.to_le_bytes().to_vec();
+        header.extend_from_slice(&1_000_000u32.to_le_bytes());
+        header.extend_from_slice(&[0; 16]);
+
+        let rt = Builder::new_current_thread().build().unwrap();
+        rt.block_on(async {
+            let mut reader = AsyncUclReader::new(&header[..]);
+            let err = reader.read_to_end(&mut Vec::new()).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        });
+    }
+
+    #[test]
+    fn async_reader_truncated() {
+        let mut sync = UclWriter::new(Vec::new());
+        sync.write_all(&[0xa5; 4096]).unwrap();
+        let compressed = sync.finish().unwrap();
+
+        let rt = Builder::new_current_thread().build().unwrap();
+        rt.block_on(async {
+            for len in [
+                0,
+                4,
+                compressed.len() - BLOCK_HEADER_LEN,
+                compressed.len() - 1,
+            ] {
+                let mut reader = AsyncUclReader::new(&compressed[..len]);
+                let err = reader.read_to_end(&mut Vec::new()).await.unwrap_err();
+                assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+            }
+        });
+    }
+}